@@ -0,0 +1,41 @@
+use criterion::{Criterion, Throughput, criterion_group, criterion_main};
+use vm_thingy::asm::assemble;
+use vm_thingy::vm::State;
+
+// counts r0 down from 32767 a hundred times, about 6.5 million instructions
+// and no I/O so only the interpreter itself is measured
+const COUNTDOWN: &str = "
+    set r1 100
+    set r0 32767      ; 3
+    add r0 r0 32767   ; 6, r0 -= 1
+    jt r0 6
+    add r1 r1 32767
+    jt r1 3
+    halt
+";
+
+fn run(state: &mut State) {
+    while !state.done() {
+        state.next().unwrap();
+    }
+}
+
+fn throughput(c: &mut Criterion) {
+    let bin = assemble(COUNTDOWN).unwrap();
+    let (mut state, _pipes) = State::init_with(&bin);
+    run(&mut state);
+
+    let mut group = c.benchmark_group("interpreter");
+    group.throughput(Throughput::Elements(state.cycles()));
+    group.sample_size(20);
+    group.bench_function("countdown", |b| {
+        b.iter(|| {
+            state.reset();
+            run(&mut state);
+        })
+    });
+    group.finish();
+}
+
+criterion_group!(benches, throughput);
+criterion_main!(benches);
@@ -0,0 +1,174 @@
+use std::fmt;
+
+use crate::vm::{Opcode, REGISTER_1, REGISTER_COUNT};
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum AssembleError {
+    UnknownMnemonic {
+        line: usize,
+        mnemonic: String,
+    },
+    InvalidOperand {
+        line: usize,
+        operand: String,
+    },
+    OperandCount {
+        line: usize,
+        expected: u16,
+        found: usize,
+    },
+}
+
+impl fmt::Display for AssembleError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AssembleError::UnknownMnemonic { line, mnemonic } => {
+                write!(f, "line {line}: Unknown Mnemonic: {mnemonic}")
+            }
+            AssembleError::InvalidOperand { line, operand } => {
+                write!(f, "line {line}: Invalid Operand: {operand}")
+            }
+            AssembleError::OperandCount {
+                line,
+                expected,
+                found,
+            } => write!(
+                f,
+                "line {line}: Expected {expected} Operands, found {found}"
+            ),
+        }
+    }
+}
+
+/// Assembles one instruction per line, e.g. `set r0 10` or `out 0x41`, into a
+/// binary in the challenge format. Literals are decimal or `0x` hex, `;` starts a comment.
+/// `.word` (or `dw`) emits its operands as raw words and `.string "..."` emits
+/// one word per character, with `\n`, `\"` and `\\` escapes
+pub fn assemble(source: &str) -> Result<Vec<u8>, AssembleError> {
+    let mut words: Vec<u16> = vec![];
+
+    for (idx, line) in source.lines().enumerate() {
+        let line_number = idx + 1;
+
+        // strings may contain `;`, so they are handled before comments are cut off
+        let trimmed = line.trim_start();
+        let (directive, text) = trimmed
+            .split_once(char::is_whitespace)
+            .unwrap_or((trimmed, ""));
+        if directive == ".string" {
+            let string = parse_string(text).ok_or_else(|| AssembleError::InvalidOperand {
+                line: line_number,
+                operand: text.trim().to_string(),
+            })?;
+            words.extend(string);
+            continue;
+        }
+
+        let code = line.split(';').next().unwrap_or("");
+        let mut tokens = code.split_whitespace();
+
+        let Some(mnemonic) = tokens.next() else {
+            continue;
+        };
+
+        if mnemonic == ".word" || mnemonic.eq_ignore_ascii_case("dw") {
+            let data = tokens
+                .map(|token| {
+                    parse_data(token).ok_or_else(|| AssembleError::InvalidOperand {
+                        line: line_number,
+                        operand: token.to_string(),
+                    })
+                })
+                .collect::<Result<Vec<u16>, _>>()?;
+
+            if data.is_empty() {
+                return Err(AssembleError::OperandCount {
+                    line: line_number,
+                    expected: 1,
+                    found: 0,
+                });
+            }
+
+            words.extend(data);
+            continue;
+        }
+
+        let opcode = parse_mnemonic(mnemonic).ok_or_else(|| AssembleError::UnknownMnemonic {
+            line: line_number,
+            mnemonic: mnemonic.to_string(),
+        })?;
+
+        let operands = tokens
+            .map(|token| {
+                parse_operand(token).ok_or_else(|| AssembleError::InvalidOperand {
+                    line: line_number,
+                    operand: token.to_string(),
+                })
+            })
+            .collect::<Result<Vec<u16>, _>>()?;
+
+        if operands.len() != opcode.operand_count() as usize {
+            return Err(AssembleError::OperandCount {
+                line: line_number,
+                expected: opcode.operand_count(),
+                found: operands.len(),
+            });
+        }
+
+        words.push(opcode as u16);
+        words.extend(operands);
+    }
+
+    Ok(words.iter().flat_map(|word| word.to_le_bytes()).collect())
+}
+
+fn parse_mnemonic(mnemonic: &str) -> Option<Opcode> {
+    (0..)
+        .map_while(|value| Opcode::try_from(value).ok())
+        .find(|opcode| opcode.mnemonic().eq_ignore_ascii_case(mnemonic))
+}
+
+fn parse_operand(token: &str) -> Option<u16> {
+    if let Some(register) = token.strip_prefix('r') {
+        let register: u16 = register.parse().ok()?;
+        return (usize::from(register) < REGISTER_COUNT).then_some(REGISTER_1 + register);
+    }
+
+    let literal = parse_number(token)?;
+    (literal < REGISTER_1).then_some(literal)
+}
+
+// data words are not operands, so they can hold any value
+fn parse_data(token: &str) -> Option<u16> {
+    parse_operand(token).or_else(|| parse_number(token))
+}
+
+fn parse_number(token: &str) -> Option<u16> {
+    match token.strip_prefix("0x") {
+        Some(hex) => u16::from_str_radix(hex, 16).ok(),
+        None => token.parse().ok(),
+    }
+}
+
+// a quoted string of ascii characters, optionally followed by a comment
+fn parse_string(text: &str) -> Option<Vec<u16>> {
+    let mut chars = text.trim_start().strip_prefix('"')?.chars();
+    let mut words = vec![];
+
+    loop {
+        let byte = match chars.next()? {
+            '"' => break,
+            '\\' => match chars.next()? {
+                'n' => b'\n',
+                '"' => b'"',
+                '\\' => b'\\',
+                _ => return None,
+            },
+            char => u8::try_from(char).ok().filter(u8::is_ascii)?,
+        };
+        words.push(u16::from(byte));
+    }
+
+    let rest = chars.as_str().trim_start();
+    (rest.is_empty() || rest.starts_with(';')).then_some(words)
+}
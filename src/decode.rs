@@ -0,0 +1,284 @@
+use std::fmt;
+
+use crate::vm::{Error, Opcode, REGISTER_COUNT, WORD_BITS, register_1, register_space};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Operand {
+    Literal(u16),
+    Register(u8),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Instruction {
+    Halt,
+    Set {
+        dst: Operand,
+        src: Operand,
+    },
+    Push {
+        src: Operand,
+    },
+    Pop {
+        dst: Operand,
+    },
+    Eq {
+        dst: Operand,
+        a: Operand,
+        b: Operand,
+    },
+    Gt {
+        dst: Operand,
+        a: Operand,
+        b: Operand,
+    },
+    Jmp {
+        target: Operand,
+    },
+    Jt {
+        cond: Operand,
+        target: Operand,
+    },
+    Jf {
+        cond: Operand,
+        target: Operand,
+    },
+    Add {
+        dst: Operand,
+        a: Operand,
+        b: Operand,
+    },
+    Mult {
+        dst: Operand,
+        a: Operand,
+        b: Operand,
+    },
+    Mod {
+        dst: Operand,
+        a: Operand,
+        b: Operand,
+    },
+    And {
+        dst: Operand,
+        a: Operand,
+        b: Operand,
+    },
+    Or {
+        dst: Operand,
+        a: Operand,
+        b: Operand,
+    },
+    Not {
+        dst: Operand,
+        src: Operand,
+    },
+    Rmem {
+        dst: Operand,
+        addr: Operand,
+    },
+    Wmem {
+        addr: Operand,
+        src: Operand,
+    },
+    Call {
+        target: Operand,
+    },
+    Ret,
+    Out {
+        src: Operand,
+    },
+    In {
+        dst: Operand,
+    },
+    Noop,
+}
+
+/// Decodes the instruction at word address `addr`.
+/// Returns it together with the word address of the following instruction
+pub fn decode(ram: &[u8], addr: u16) -> Result<(Instruction, u16), Error> {
+    decode_with::<WORD_BITS, REGISTER_COUNT>(ram, addr)
+}
+
+// decode for any architecture a `Machine` can have
+pub(crate) fn decode_with<const WORD_BITS: u8, const REGISTER_COUNT: usize>(
+    ram: &[u8],
+    addr: u16,
+) -> Result<(Instruction, u16), Error> {
+    let opcode = Opcode::try_from(read_word(ram, addr)?)?;
+    let operand = |n: u16| read_operand::<WORD_BITS, REGISTER_COUNT>(ram, addr.wrapping_add(n));
+
+    use Instruction as I;
+    let instruction = match opcode {
+        Opcode::Halt => I::Halt,
+        Opcode::Set => I::Set {
+            dst: operand(1)?,
+            src: operand(2)?,
+        },
+        Opcode::Push => I::Push { src: operand(1)? },
+        Opcode::Pop => I::Pop { dst: operand(1)? },
+        Opcode::Eq => I::Eq {
+            dst: operand(1)?,
+            a: operand(2)?,
+            b: operand(3)?,
+        },
+        Opcode::Gt => I::Gt {
+            dst: operand(1)?,
+            a: operand(2)?,
+            b: operand(3)?,
+        },
+        Opcode::Jmp => I::Jmp {
+            target: operand(1)?,
+        },
+        Opcode::Jt => I::Jt {
+            cond: operand(1)?,
+            target: operand(2)?,
+        },
+        Opcode::Jf => I::Jf {
+            cond: operand(1)?,
+            target: operand(2)?,
+        },
+        Opcode::Add => I::Add {
+            dst: operand(1)?,
+            a: operand(2)?,
+            b: operand(3)?,
+        },
+        Opcode::Mult => I::Mult {
+            dst: operand(1)?,
+            a: operand(2)?,
+            b: operand(3)?,
+        },
+        Opcode::Mod => I::Mod {
+            dst: operand(1)?,
+            a: operand(2)?,
+            b: operand(3)?,
+        },
+        Opcode::And => I::And {
+            dst: operand(1)?,
+            a: operand(2)?,
+            b: operand(3)?,
+        },
+        Opcode::Or => I::Or {
+            dst: operand(1)?,
+            a: operand(2)?,
+            b: operand(3)?,
+        },
+        Opcode::Not => I::Not {
+            dst: operand(1)?,
+            src: operand(2)?,
+        },
+        Opcode::Rmem => I::Rmem {
+            dst: operand(1)?,
+            addr: operand(2)?,
+        },
+        Opcode::Wmem => I::Wmem {
+            addr: operand(1)?,
+            src: operand(2)?,
+        },
+        Opcode::Call => I::Call {
+            target: operand(1)?,
+        },
+        Opcode::Ret => I::Ret,
+        Opcode::Out => I::Out { src: operand(1)? },
+        Opcode::In => I::In { dst: operand(1)? },
+        Opcode::Noop => I::Noop,
+    };
+
+    Ok((instruction, addr.wrapping_add(opcode.length())))
+}
+
+impl Instruction {
+    pub fn opcode(&self) -> Opcode {
+        match self {
+            Instruction::Halt => Opcode::Halt,
+            Instruction::Set { .. } => Opcode::Set,
+            Instruction::Push { .. } => Opcode::Push,
+            Instruction::Pop { .. } => Opcode::Pop,
+            Instruction::Eq { .. } => Opcode::Eq,
+            Instruction::Gt { .. } => Opcode::Gt,
+            Instruction::Jmp { .. } => Opcode::Jmp,
+            Instruction::Jt { .. } => Opcode::Jt,
+            Instruction::Jf { .. } => Opcode::Jf,
+            Instruction::Add { .. } => Opcode::Add,
+            Instruction::Mult { .. } => Opcode::Mult,
+            Instruction::Mod { .. } => Opcode::Mod,
+            Instruction::And { .. } => Opcode::And,
+            Instruction::Or { .. } => Opcode::Or,
+            Instruction::Not { .. } => Opcode::Not,
+            Instruction::Rmem { .. } => Opcode::Rmem,
+            Instruction::Wmem { .. } => Opcode::Wmem,
+            Instruction::Call { .. } => Opcode::Call,
+            Instruction::Ret => Opcode::Ret,
+            Instruction::Out { .. } => Opcode::Out,
+            Instruction::In { .. } => Opcode::In,
+            Instruction::Noop => Opcode::Noop,
+        }
+    }
+
+    /// Operands in the order they are encoded
+    pub fn operands(&self) -> Vec<Operand> {
+        use Instruction as I;
+        match *self {
+            I::Halt | I::Ret | I::Noop => vec![],
+            I::Push { src: a } | I::Pop { dst: a } | I::Out { src: a } | I::In { dst: a } => {
+                vec![a]
+            }
+            I::Jmp { target: a } | I::Call { target: a } => vec![a],
+            I::Set { dst: a, src: b } | I::Not { dst: a, src: b } => vec![a, b],
+            I::Jt { cond: a, target: b } | I::Jf { cond: a, target: b } => vec![a, b],
+            I::Rmem { dst: a, addr: b } | I::Wmem { addr: a, src: b } => vec![a, b],
+            I::Eq { dst, a, b }
+            | I::Gt { dst, a, b }
+            | I::Add { dst, a, b }
+            | I::Mult { dst, a, b }
+            | I::Mod { dst, a, b }
+            | I::And { dst, a, b }
+            | I::Or { dst, a, b } => vec![dst, a, b],
+        }
+    }
+}
+
+impl fmt::Display for Operand {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Operand::Literal(value) => write!(f, "{value:#06x}"),
+            Operand::Register(register) => write!(f, "r{register}"),
+        }
+    }
+}
+
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.opcode().mnemonic())?;
+        for operand in self.operands() {
+            write!(f, " {operand}")?;
+        }
+        Ok(())
+    }
+}
+
+fn read_operand<const WORD_BITS: u8, const REGISTER_COUNT: usize>(
+    ram: &[u8],
+    addr: u16,
+) -> Result<Operand, Error> {
+    let register_1 = register_1(WORD_BITS);
+    match read_word(ram, addr)? {
+        word if word < register_1 => Ok(Operand::Literal(word)),
+        word if word <= register_space(WORD_BITS, REGISTER_COUNT) => {
+            Ok(Operand::Register((word - register_1) as u8))
+        }
+        word => Err(Error::InvalidUint15 {
+            value: word,
+            at: Some(addr.wrapping_mul(2)),
+        }),
+    }
+}
+
+fn read_word(ram: &[u8], addr: u16) -> Result<u16, Error> {
+    let ptr = addr as usize * 2;
+    let Some(&[byte1, byte2]) = ram.get(ptr..ptr + 2) else {
+        return Err(Error::InvalidAddress {
+            value: addr,
+            at: None,
+        });
+    };
+    Ok(u16::from_le_bytes([byte1, byte2]))
+}
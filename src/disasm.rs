@@ -0,0 +1,49 @@
+use crate::decode::{Instruction, Operand, decode};
+
+/// Disassembles the words in `start..end` of `ram`, one line per instruction.
+/// Addresses are word addresses, the same space jump targets live in.
+/// Words that don't decode to an instruction are emitted as `.word` data.
+/// `out` of a literal character and `in` get a comment saying what they do
+pub fn disassemble(ram: &[u8], start: u16, end: u16) -> Vec<(u16, String)> {
+    let mut lines = vec![];
+    let mut addr = start;
+
+    while addr < end && (addr as usize) < ram.len() / 2 {
+        match decode(ram, addr) {
+            Ok((instruction, next)) if next > addr => {
+                let line = match comment(&instruction) {
+                    Some(comment) => format!("{instruction}  ; {comment}"),
+                    None => instruction.to_string(),
+                };
+                lines.push((addr, line));
+                addr = next;
+            }
+            _ => {
+                let word = u16::from_le_bytes([ram[addr as usize * 2], ram[addr as usize * 2 + 1]]);
+                lines.push((addr, format!(".word {word:#06x}")));
+                addr += 1;
+            }
+        }
+    }
+
+    lines
+}
+
+// what the character printed or read is, when it's known without running
+fn comment(instruction: &Instruction) -> Option<String> {
+    match *instruction {
+        Instruction::Out {
+            src: Operand::Literal(char @ 0..0x80),
+        } => Some(format!("'{}'", (char as u8).escape_ascii())),
+        Instruction::In { .. } => Some("read a character".to_string()),
+        _ => None,
+    }
+}
+
+/// Renders a disassembly listing like `0x0006  jt r0 0x0141`
+pub fn format_listing(lines: &[(u16, String)]) -> String {
+    lines
+        .iter()
+        .map(|(addr, line)| format!("{addr:#06x}  {line}\n"))
+        .collect()
+}
@@ -0,0 +1,5 @@
+pub mod asm;
+pub mod decode;
+pub mod disasm;
+pub mod solver;
+pub mod vm;
@@ -1,21 +1,153 @@
-use crate::solver::solve;
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, PipeReader};
+use std::sync::Arc;
+use std::sync::atomic::AtomicBool;
 
-mod solver;
-mod vm;
+use vm_thingy::solver::{Controller, Transcript, explore, interactive, replay, solve};
+use vm_thingy::vm;
 
+#[cfg(feature = "embedded-binary")]
+const BINARY: Option<&[u8]> = Some(include_bytes!("../challenge.bin"));
+#[cfg(not(feature = "embedded-binary"))]
+const BINARY: Option<&[u8]> = None;
+
+// usage: vm-thingy [binary] [--script commands.txt | --interactive | --explore] [--transcript log.txt]
+// the binary can only be left out when built with the embedded-binary feature
 fn main() {
-    const BINARY: &[u8; 60100] = include_bytes!("../challenge.bin");
-    let (mut state, (stdout, stdin)) = vm::State::init_with(BINARY);
+    let mut binary_path = None;
+    let mut script_path = None;
+    let mut transcript_path = None;
+    let mut mode = Mode::Solve;
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--script" => script_path = args.next(),
+            "--interactive" => mode = Mode::Interactive,
+            "--explore" => mode = Mode::Explore,
+            "--transcript" => transcript_path = args.next(),
+            _ => binary_path = Some(arg),
+        }
+    }
+
+    let binary = match (binary_path, BINARY) {
+        (Some(path), _) => read_or_exit(&path),
+        (None, Some(binary)) => binary.to_vec(),
+        (None, None) => {
+            eprintln!("No binary given, pass its path or build with --features embedded-binary");
+            std::process::exit(1);
+        }
+    };
+    let script = script_path.map(|path| String::from_utf8_lossy(&read_or_exit(&path)).into_owned());
+    let transcript = transcript_path.map(|path| match File::create(&path) {
+        Ok(file) => BufWriter::new(file),
+        Err(err) => {
+            eprintln!("Could not create {path}: {err}");
+            std::process::exit(1);
+        }
+    });
+
+    let (mut state, (stdout, stdin)) = vm::State::init_with(&binary);
+    state.detect_data_execution(true);
+    stop_on_ctrl_c(state.interrupt_handle());
 
+    // dropping the state when the program halts closes the stdout pipe, which ends the solver
     let vm_thread = std::thread::spawn(move || {
-        loop {
-            state.next();
-            state.done().then(||state.reset());
+        while !state.done() {
+            if let Err(err) = state.next() {
+                eprintln!("{err}");
+                // show what execution ended up in
+                if let vm::Error::ExecutingData(pc) = err {
+                    let _ = state.dump_memory(
+                        pc.saturating_sub(32),
+                        pc.saturating_add(32),
+                        &mut io::stderr(),
+                    );
+                }
+                break;
+            }
+        }
+        if *state.halt_reason() == vm::HaltReason::RetEmptyStack {
+            eprintln!("Returned with an empty stack");
         }
     });
 
-    let _ = std::thread::spawn(move || solve(stdout, stdin));
+    let solver_thread = std::thread::spawn(move || match transcript {
+        Some(log) => play_game(stdout, Transcript::new(stdin, log), script, mode),
+        None => play_game(stdout, stdin, script, mode),
+    });
 
     let _ = vm_thread.join();
+    let _ = solver_thread.join();
     println!("Terminated");
 }
+
+// in interactive mode the game's output is printed on a different thread
+// than the commands are sent from, so a transcript only gets the commands
+fn play_game(
+    stdout: PipeReader,
+    controller: impl Controller + Send + 'static,
+    script: Option<String>,
+    mode: Mode,
+) {
+    match (script, mode) {
+        (Some(script), _) => replay(stdout, controller, &script, io::stdin().lock()),
+        (None, Mode::Interactive) => interactive(stdout, controller, BufReader::new(io::stdin())),
+        (None, Mode::Explore) => {
+            explore(stdout, controller);
+        }
+        (None, Mode::Solve) => {
+            solve(stdout, controller);
+        }
+    }
+}
+
+// what the game is played with when there's no script
+#[derive(Clone, Copy)]
+enum Mode {
+    Solve,
+    Interactive,
+    Explore,
+}
+
+// the first Ctrl-C interrupts the VM, which ends it like an error would but
+// flushes its output first. a second one kills the process as usual, for when
+// the VM is stuck waiting for input and never gets to check the flag
+#[cfg(unix)]
+fn stop_on_ctrl_c(interrupt: Arc<AtomicBool>) {
+    use std::sync::OnceLock;
+    use std::sync::atomic::Ordering;
+
+    static INTERRUPT: OnceLock<Arc<AtomicBool>> = OnceLock::new();
+
+    extern "C" fn on_sigint(_: libc::c_int) {
+        if let Some(interrupt) = INTERRUPT.get() {
+            interrupt.store(true, Ordering::Relaxed);
+        }
+        // SAFETY: signal is async-signal-safe and SIG_DFL is a valid handler
+        unsafe {
+            libc::signal(libc::SIGINT, libc::SIG_DFL);
+        }
+    }
+
+    let _ = INTERRUPT.set(interrupt);
+    // SAFETY: on_sigint only touches an atomic and calls signal
+    unsafe {
+        libc::signal(
+            libc::SIGINT,
+            on_sigint as extern "C" fn(libc::c_int) as libc::sighandler_t,
+        );
+    }
+}
+
+#[cfg(not(unix))]
+fn stop_on_ctrl_c(_: Arc<AtomicBool>) {}
+
+fn read_or_exit(path: &str) -> Vec<u8> {
+    match std::fs::read(path) {
+        Ok(bytes) => bytes,
+        Err(err) => {
+            eprintln!("Could not read {path}: {err}");
+            std::process::exit(1);
+        }
+    }
+}
@@ -1,72 +1,577 @@
-use std::io::{PipeReader, Read, Write};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::io::{self, BufRead, BufReader, PipeReader, Write};
 
-pub fn solve(mut stdout: PipeReader, mut controller: impl Controller) {
-    let mut things: Vec<String> = vec![];
-    loop {
-        let line = read_line(&mut stdout);
-        println!("{line}");
-        let is_thing = line.chars().nth(0).map(|c| c == '-').unwrap_or(false);
+use crate::vm::{Error, Opcode, State};
+
+mod inventory;
+mod map;
+mod transcript;
+mod vault;
+
+pub use inventory::Inventory;
+pub use map::{Map, Room};
+pub use transcript::Transcript;
+pub use vault::{ORB_START, VAULT_GRID, VAULT_WEIGHT, VaultRoom, solve_vault, walk_vault};
+
+/// What the solver learned over a playthrough
+#[derive(Debug, Default)]
+pub struct Playthrough {
+    pub map: Map,
+    /// Codes found in the game's output, in the order they showed up
+    pub codes: Vec<String>,
+    pub deaths: u32,
+    /// Number of commands sent
+    pub turns: u64,
+    /// What the game last listed as held
+    pub inventory: Inventory,
+}
+
+/// Plays the game until the program halts. The game's output is printed
+/// along with every command sent, prefixed with its turn
+pub fn solve(stdout: PipeReader, controller: impl Controller) -> Playthrough {
+    play(stdout, controller, false)
+}
+
+/// Plays like `solve`, but also walks the map on its own: whenever nothing else
+/// needs doing it takes the next step of `Map::explore_step`.
+/// Stops once every exit that can be reached has been taken
+pub fn explore(stdout: PipeReader, controller: impl Controller) -> Playthrough {
+    play(stdout, controller, true)
+}
+
+fn play(stdout: PipeReader, controller: impl Controller, exploring: bool) -> Playthrough {
+    let mut stdout = BufReader::new(stdout);
+    let mut controller = Transcript::new(controller, io::stdout());
+    let mut map = Map::new();
+    let mut codes: Vec<String> = vec![];
+    // things picked up, things asked for but not confirmed yet in the order
+    // they were asked for, and things the game said weren't there
+    let mut held: HashSet<String> = HashSet::new();
+    let mut taking: VecDeque<String> = VecDeque::new();
+    let mut missing: HashSet<String> = HashSet::new();
+    // the game's own listing replaces the guesses above whenever it's asked for
+    let mut inventory = Inventory::new();
+    let mut check_inventory = false;
+    // coins are looked at to learn their value, answers come back in order
+    let mut coin_values: HashMap<String, u16> = HashMap::new();
+    let mut looking: VecDeque<String> = VecDeque::new();
+    let mut at_monument = false;
+    let mut coins_placed = false;
+    let mut vault_walked = false;
+    // walking on in the dark gets the player eaten, so the lantern is lit first
+    let mut in_darkness = false;
+    let mut light_lantern = false;
+    let mut deaths = 0;
+    let mut recovery: VecDeque<String> = VecDeque::new();
+    // the game prompts once up front and then once per command
+    let mut prompts: u64 = 0;
+    while let Some(line) = read_line(&mut stdout) {
+        controller.on_output(&line);
+        note_codes(&mut codes, &line);
+        map.read_line(&line);
+        if inventory.read_line(&line) {
+            held.clone_from(inventory.items());
+        }
+        if is_death(&line) {
+            // the rooms stay the same but everything picked up is gone
+            deaths += 1;
+            held.clear();
+            taking.clear();
+            missing.clear();
+            looking.clear();
+            at_monument = false;
+            coins_placed = false;
+            vault_walked = false;
+            in_darkness = false;
+            light_lantern = false;
+            check_inventory = true;
+            map.record_death();
+            recovery.extend(controller.on_death(&line));
+            continue;
+        }
+        match line.as_str() {
+            "Taken." => held.extend(taking.pop_front()),
+            "You see no such item here." => missing.extend(taking.pop_front()),
+            _ => {}
+        }
+        if let Some(value) = coin_value(&line)
+            && let Some(coin) = looking.pop_front()
+        {
+            coin_values.insert(coin, value);
+        }
+        if line.starts_with("== ") {
+            at_monument = false;
+        }
+        if line.ends_with(MONUMENT) {
+            at_monument = true;
+        }
+        if is_darkness(&line) {
+            in_darkness = true;
+        }
         if line == "What do you do?" {
-            things.iter().for_each(|thing| controller.take_thing(thing));
+            prompts += 1;
+            let turn = controller.turn();
+            if let Some(command) = recovery.pop_front() {
+                send_command(&mut controller, &command);
+                continue;
+            }
+            if in_darkness {
+                in_darkness = false;
+                if held.contains(LANTERN) {
+                    controller.use_thing(LANTERN);
+                    check_inventory = true;
+                    continue;
+                }
+                // exploring comes back once there's light, which changes the
+                // description and so makes it a different room on the map
+                if let Some(exit) = map.retreat() {
+                    controller.go_place(&exit);
+                    continue;
+                }
+            }
+            if check_inventory {
+                controller.inv();
+                check_inventory = false;
+                continue;
+            }
+            if held.contains(EMPTY_LANTERN) && held.contains(CAN) {
+                controller.use_thing(CAN);
+                // the inventory check after lighting it confirms this
+                held.remove(EMPTY_LANTERN);
+                held.remove(CAN);
+                held.insert(LANTERN.to_string());
+                light_lantern = true;
+                continue;
+            }
+            if light_lantern {
+                controller.use_thing(LANTERN);
+                light_lantern = false;
+                check_inventory = true;
+                continue;
+            }
+            let things = map.current().map(|room| &room.things[..]).unwrap_or(&[]);
+            for thing in things {
+                if !held.contains(thing) && !taking.contains(thing) && !missing.contains(thing) {
+                    controller.take_thing(thing);
+                    taking.push_back(thing.clone());
+                }
+            }
+            let mut unknown: Vec<&String> = held
+                .iter()
+                .filter(|thing| is_coin(thing) && !coin_values.contains_key(*thing))
+                .collect();
+            unknown.sort();
+            for coin in unknown {
+                if !looking.contains(coin) {
+                    controller.command(&format!("look {coin}"));
+                    looking.push_back(coin.clone());
+                }
+            }
+            let coins: Vec<(&str, u16)> = held
+                .iter()
+                .filter_map(|thing| Some((thing.as_str(), *coin_values.get(thing)?)))
+                .collect();
+            let coins_ready = coins.len() == MONUMENT_SLOTS;
+            if at_monument && !coins_placed && coins_ready {
+                coins_placed = place_coins(&mut controller, &coins);
+                if coins_placed {
+                    map.unblock();
+                    held.retain(|thing| !is_coin(thing));
+                    check_inventory = true;
+                }
+            }
+            let in_antechamber = map
+                .current()
+                .is_some_and(|room| room.name == "Vault Antechamber");
+            if in_antechamber && held.contains("orb") && !vault_walked {
+                vault_walked = walk_vault(&mut controller);
+            }
+            // one move once every command got its answer, so the map can tell
+            // which exit led where
+            map.set_light(held.contains(LIT_LANTERN));
+            let answered = prompts > turn;
+            if exploring && answered && controller.turn() == turn {
+                // the coins only go in at the monument, and behind its door is more to see
+                let to_monument = coins_ready
+                    && !coins_placed
+                    && map.step_toward(&mut controller, |room| {
+                        room.description
+                            .lines()
+                            .any(|line| line.ends_with(MONUMENT))
+                    });
+                if !to_monument && !map.explore_step(&mut controller) {
+                    break;
+                }
+            }
         }
-        if !is_thing {
+    }
+    print_codes(&codes);
+    Playthrough {
+        map,
+        codes,
+        deaths,
+        turns: controller.turn(),
+        inventory,
+    }
+}
+
+/// Sends the commands in `script`, one per line, each time the game asks for
+/// input. Once the script runs out the commands are read from `fallback` instead
+pub fn replay(
+    stdout: PipeReader,
+    mut controller: impl Controller,
+    script: &str,
+    mut fallback: impl BufRead,
+) {
+    let mut stdout = BufReader::new(stdout);
+    let mut commands = script
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty());
+    let mut codes: Vec<String> = vec![];
+    while let Some(line) = read_line(&mut stdout) {
+        println!("{line}");
+        controller.on_output(&line);
+        note_codes(&mut codes, &line);
+        if line != "What do you do?" {
             continue;
         }
-        let thing: String = line.chars().skip(2).collect();
-        things.push(thing);
+        let command = match commands.next() {
+            Some(command) => {
+                println!("{command}");
+                command.to_string()
+            }
+            None => match read_line(&mut fallback) {
+                Some(command) => command,
+                None => break,
+            },
+        };
+        send_command(&mut controller, &command);
     }
+    print_codes(&codes);
 }
 
-#[allow(dead_code)]
-pub trait Controller {
-    fn help(&mut self) -> ();
-    fn look(&mut self) -> ();
-    fn inv(&mut self) -> ();
-    fn go_place(&mut self, place: &String) -> ();
-    fn take_thing(&mut self, thing: &String) -> ();
-    fn drop_thing(&mut self, thing: &String) -> ();
-    fn use_thing(&mut self, thing: &String) -> ();
+/// Prints the game's output while forwarding every line of `input` to it,
+/// for playing the game by hand
+pub fn interactive(
+    stdout: PipeReader,
+    mut controller: impl Controller + Send + 'static,
+    mut input: impl BufRead + Send + 'static,
+) {
+    // input has to be read on its own thread so waiting for the next line
+    // never holds up printing the game's output. it isn't joined since it
+    // may be stuck waiting on input after the game is over
+    std::thread::spawn(move || {
+        while let Some(command) = read_line(&mut input) {
+            send_command(&mut controller, &command);
+        }
+    });
+
+    let mut stdout = BufReader::new(stdout);
+    let mut codes: Vec<String> = vec![];
+    while let Some(line) = read_line(&mut stdout) {
+        println!("{line}");
+        note_codes(&mut codes, &line);
+    }
+    print_codes(&codes);
+}
+
+/// Sends a command as it would be typed into the game through the
+/// matching controller method
+pub fn send_command(controller: &mut impl Controller, command: &str) {
+    match command.split_once(' ') {
+        Some(("go", place)) => controller.go_place(place),
+        Some(("take", thing)) => controller.take_thing(thing),
+        Some(("drop", thing)) => controller.drop_thing(thing),
+        Some(("use", thing)) => controller.use_thing(thing),
+        _ => match command {
+            "help" => controller.help(),
+            "look" => controller.look(),
+            "inv" => controller.inv(),
+            _ => controller.command(command),
+        },
+    }
+}
+
+// the entry of a "- thing" list line, trimmed of stray whitespace and a
+// trailing period so it can be sent back to the game as is
+fn list_entry(line: &str) -> Option<String> {
+    let entry = line.strip_prefix("- ")?;
+    let entry = entry.trim().trim_end_matches('.').trim_end();
+    let words: Vec<&str> = entry.split_whitespace().collect();
+    (!words.is_empty()).then(|| words.join(" "))
+}
+
+// lines the game prints when the player dies
+const DEATHS: [&str; 2] = ["You have been eaten by a grue.", "You have been killed"];
+
+/// Whether `line` says the player died
+pub fn is_death(line: &str) -> bool {
+    DEATHS.iter().any(|death| line.starts_with(death))
+}
+
+// lines the game prints when the player walks into the dark without a light
+const DARKNESS: [&str; 2] = [
+    "It is pitch black.",
+    "== Fumbling around in the darkness ==",
+];
+
+// the lantern is picked up empty and filled from the can
+const EMPTY_LANTERN: &str = "empty lantern";
+const CAN: &str = "can";
+// the lantern once the can has filled it, it's called "lit lantern" once used
+const LANTERN: &str = "lantern";
+const LIT_LANTERN: &str = "lit lantern";
+
+/// Whether `line` warns that the player is in the dark
+pub fn is_darkness(line: &str) -> bool {
+    DARKNESS.iter().any(|darkness| line.starts_with(darkness))
+}
+
+/// Words in `line` that look like challenge codes: 12 letters and digits
+/// with upper case letters past the first
+pub fn find_codes(line: &str) -> impl Iterator<Item = &str> {
+    line.split(|c: char| !c.is_ascii_alphanumeric())
+        .filter(|word| word.len() == 12)
+        .filter(|word| word.chars().any(|c| c.is_ascii_lowercase()))
+        .filter(|word| word.chars().skip(1).any(|c| c.is_ascii_uppercase()))
+}
+
+fn note_codes(codes: &mut Vec<String>, line: &str) {
+    for code in find_codes(line) {
+        if !codes.iter().any(|known| known == code) {
+            println!("FOUND CODE: {code}");
+            codes.push(code.to_string());
+        }
+    }
+}
+
+fn print_codes(codes: &[String]) {
+    if codes.is_empty() {
+        return;
+    }
+    println!("Codes found:");
+    codes.iter().for_each(|code| println!("- {code}"));
 }
 
+// the equation on the monument in the ruins
+const MONUMENT: &str = "_ + _ * _^2 + _^3 - _ = 399";
+
+const MONUMENT_SLOTS: usize = 5;
+
+const NUMBERS: [&str; 9] = [
+    "one", "two", "three", "four", "five", "six", "seven", "eight", "nine",
+];
+
+// polygons by their number of sides
+const SHAPES: [(&str, u16); 7] = [
+    ("triangle", 3),
+    ("square", 4),
+    ("pentagon", 5),
+    ("hexagon", 6),
+    ("heptagon", 7),
+    ("octagon", 8),
+    ("nonagon", 9),
+];
+
+fn is_coin(thing: &str) -> bool {
+    thing.ends_with(" coin")
+}
+
+/// Value of a coin going by its description, which either counts the dots
+/// on its face ("It has two dots on one side.") or names the shape on it
+pub fn coin_value(description: &str) -> Option<u16> {
+    let words: Vec<String> = description
+        .split(|c: char| !c.is_ascii_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(str::to_ascii_lowercase)
+        .collect();
+
+    words.iter().enumerate().find_map(|(i, word)| {
+        if let Some((_, sides)) = SHAPES.iter().find(|(shape, _)| shape == word) {
+            return Some(*sides);
+        }
+        if word != "dot" && word != "dots" {
+            return None;
+        }
+        let count = words.get(i.checked_sub(1)?)?;
+        match NUMBERS.iter().position(|number| number == count) {
+            Some(position) => Some(position as u16 + 1),
+            None => count.parse().ok(),
+        }
+    })
+}
+
+/// Finds the order the coins, given with their values, have to go into the
+/// monument so that `a + b * c^2 + d^3 - e = 399` holds
+pub fn solve_coins<'a>(coins: &[(&'a str, u16)]) -> Option<Vec<&'a str>> {
+    if coins.len() != MONUMENT_SLOTS {
+        return None;
+    }
+    let values: Vec<i32> = coins.iter().map(|&(_, value)| value as i32).collect();
+
+    let mut order: Vec<usize> = (0..coins.len()).collect();
+    permute(&mut order, 0, &|order: &[usize]| {
+        let [a, b, c, d, e] = [0, 1, 2, 3, 4].map(|i| values[order[i]]);
+        a + b * c.pow(2) + d.pow(3) - e == 399
+    })
+    .then(|| order.iter().map(|&i| coins[i].0).collect())
+}
+
+/// Puts the coins into the monument in the right order.
+/// Returns false if no order satisfies the equation
+pub fn place_coins(controller: &mut impl Controller, coins: &[(&str, u16)]) -> bool {
+    let Some(order) = solve_coins(coins) else {
+        return false;
+    };
+    let order: Vec<String> = order.iter().map(|coin| coin.to_string()).collect();
+    controller.use_sequence(&order);
+    true
+}
+
+// tries every ordering of items[k..], leaving items in the first one
+// that satisfies check
+fn permute(items: &mut [usize], k: usize, check: &impl Fn(&[usize]) -> bool) -> bool {
+    if k == items.len() {
+        return check(items);
+    }
+    for i in k..items.len() {
+        items.swap(k, i);
+        if permute(items, k + 1, check) {
+            return true;
+        }
+        items.swap(k, i);
+    }
+    false
+}
+
+// word address of the call into the teleporter verification
+const TELEPORTER_CALL: u16 = 0x1571;
+// word address of the verification routine itself
+const TELEPORTER_CHECK: u16 = 0x178b;
+// `set r0 6; ret`, the result the caller compares against
+const TELEPORTER_PATCH: [u16; 4] = [1, 0x8000, 6, 18];
+
+const MODULUS: usize = 1 << 15;
+
+/// Result of the teleporter verification for the energy level `energy` in r7.
+/// The routine is `f(0, b) = b + 1`, `f(a, 0) = f(a - 1, r7)`,
+/// `f(a, b) = f(a - 1, f(a, b - 1))`, called as `f(4, 1)`
+pub fn teleporter_check(energy: u16) -> u16 {
+    // the recursion is far too deep to run as is and a HashMap memo is
+    // too slow to repeat for every energy level, so build the memo one
+    // row of `a` at a time: row a only ever looks up values in row a - 1
+    let mut row: Vec<u16> = (1..=MODULUS as u16).map(|b| b % MODULUS as u16).collect();
+    let mut next = vec![0; MODULUS];
+    for _ in 1..4 {
+        next[0] = row[energy as usize];
+        for b in 1..MODULUS {
+            next[b] = row[next[b - 1] as usize];
+        }
+        std::mem::swap(&mut row, &mut next);
+    }
+    row[row[energy as usize] as usize]
+}
+
+/// Searches for the energy level that passes the teleporter verification
+pub fn find_teleporter_energy() -> Option<u16> {
+    (1..MODULUS as u16).find(|&energy| teleporter_check(energy) == 6)
+}
+
+/// Sets r7 to `energy` and replaces the verification routine with one
+/// that returns the expected result straight away.
+/// Only valid once the program has decrypted itself
+pub fn fix_teleporter(state: &mut State, energy: u16) -> Result<(), Error> {
+    let call = state.read_word(TELEPORTER_CALL)?;
+    if call != Opcode::Call as u16 || state.read_word(TELEPORTER_CALL + 1)? != TELEPORTER_CHECK {
+        return Err(Error::InvalidInstruction(call));
+    }
+
+    state.set_register(7, energy)?;
+    state.apply_patch(TELEPORTER_CHECK, &TELEPORTER_PATCH)
+}
+
+/// Sends commands to the game
+pub trait Controller: Observer {
+    fn help(&mut self);
+    fn look(&mut self);
+    fn inv(&mut self);
+    fn go_place(&mut self, place: &str);
+    fn take_thing(&mut self, thing: &str);
+    fn drop_thing(&mut self, thing: &str);
+    fn use_thing(&mut self, thing: &str);
+    /// Any other command, sent as is
+    fn command(&mut self, command: &str);
+
+    /// Uses each of `things` in order, for puzzles where the order matters
+    fn use_sequence(&mut self, things: &[String]) {
+        things.iter().for_each(|thing| self.use_thing(thing));
+    }
+}
+
+/// Hears back from the game, for controllers that react to what it prints
+pub trait Observer {
+    /// Called with every line the game prints
+    fn on_output(&mut self, _line: &str) {}
+    /// Called when the player dies, with the line that said so.
+    /// Returns the commands to send, one per prompt, once the game asks for input again
+    fn on_death(&mut self, _line: &str) -> Vec<String> {
+        vec![]
+    }
+}
+
+impl<T: Write> Observer for T {}
+
 impl<T: Write> Controller for T {
-    fn help(&mut self) -> () {
-        let _ = self.write(format!("help\n").as_bytes());
+    fn help(&mut self) {
+        write_command(self, "help");
+    }
+
+    fn look(&mut self) {
+        write_command(self, "look");
     }
 
-    fn look(&mut self) -> () {
-        let _ = self.write(format!("look\n").as_bytes());
+    fn inv(&mut self) {
+        write_command(self, "inv");
     }
 
-    fn inv(&mut self) -> () {
-        let _ = self.write(format!("inv\n").as_bytes());
+    fn go_place(&mut self, place: &str) {
+        write_command(self, &format!("go {place}"));
     }
 
-    fn go_place(&mut self, place: &String) -> () {
-        let _ = self.write(format!("go {place}\n").as_bytes());
+    fn take_thing(&mut self, thing: &str) {
+        write_command(self, &format!("take {thing}"));
     }
 
-    fn take_thing(&mut self, thing: &String) -> () {
-        let _ = self.write(format!("take {thing}\n").as_bytes());
+    fn drop_thing(&mut self, thing: &str) {
+        write_command(self, &format!("drop {thing}"));
     }
 
-    fn drop_thing(&mut self, thing: &String) -> () {
-        let _ = self.write(format!("drop {thing}\n").as_bytes());
+    fn use_thing(&mut self, thing: &str) {
+        write_command(self, &format!("use {thing}"));
     }
 
-    fn use_thing(&mut self, thing: &String) -> () {
-        let _ = self.write(format!("use {thing}\n").as_bytes());
+    fn command(&mut self, command: &str) {
+        write_command(self, command);
     }
 }
 
-fn read_line(reader: &mut dyn Read) -> String {
-    let mut buf: Vec<char> = vec![];
-    for byte in reader.bytes() {
-        let Ok(byte) = byte else { unreachable!() };
-        let char = byte as char;
-        if char == '\n' {
-            break;
-        };
-        buf.push(char);
+// flushed right away so a buffered writer can't hold a command back while
+// the solver waits for the answer to it
+fn write_command(writer: &mut impl Write, command: &str) {
+    let _ = writer
+        .write_all(format!("{command}\n").as_bytes())
+        .and_then(|()| writer.flush());
+}
+
+// returns None once the pipe is closed or broken
+fn read_line(reader: &mut impl BufRead) -> Option<String> {
+    let mut buf: Vec<u8> = vec![];
+    let Ok(1..) = reader.read_until(b'\n', &mut buf) else {
+        return None;
+    };
+    if buf.last() == Some(&b'\n') {
+        buf.pop();
     }
-    buf.iter().collect()
+    Some(String::from_utf8_lossy(&buf).into_owned())
 }
@@ -0,0 +1,59 @@
+use std::collections::HashSet;
+
+use super::list_entry;
+
+// what the game answers `inv` with, the items follow as "- item" lines
+const HEADER: &str = "Your inventory:";
+const EMPTY: &str = "Your inventory is empty";
+
+/// What the game last listed as held in response to `inv`
+#[derive(Debug, Default)]
+pub struct Inventory {
+    items: HashSet<String>,
+    // items of a listing that's still being read
+    reading: Option<HashSet<String>>,
+}
+
+impl Inventory {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Feeds one line of game output, returns true once it finished a listing
+    pub fn read_line(&mut self, line: &str) -> bool {
+        if line.starts_with(EMPTY) {
+            self.items.clear();
+            self.reading = None;
+            return true;
+        }
+
+        if line == HEADER {
+            self.reading = Some(HashSet::new());
+            return false;
+        }
+
+        let Some(reading) = &mut self.reading else {
+            return false;
+        };
+
+        match list_entry(line) {
+            Some(item) => {
+                reading.insert(item);
+                false
+            }
+            // a header with nothing after it is an empty inventory as well
+            None => {
+                self.items = self.reading.take().unwrap_or_default();
+                true
+            }
+        }
+    }
+
+    pub fn items(&self) -> &HashSet<String> {
+        &self.items
+    }
+
+    pub fn contains(&self, item: &str) -> bool {
+        self.items.contains(item)
+    }
+}
@@ -0,0 +1,409 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+
+use super::{Controller, is_darkness, list_entry};
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Room {
+    pub name: String,
+    pub description: String,
+    pub things: Vec<String>,
+    pub exits: Vec<String>,
+    /// Exits that have been taken and the fingerprint of the room they led to
+    pub traversed: HashMap<String, String>,
+}
+
+impl Room {
+    /// Tells apart rooms that share a name, like the ones in the twisty maze,
+    /// by their description and set of exits
+    pub fn fingerprint(&self) -> String {
+        let mut exits: Vec<&str> = self.exits.iter().map(String::as_str).collect();
+        exits.sort_unstable();
+        format!("{}\n{}\n{}", self.name, self.description, exits.join("\n"))
+    }
+
+    /// Whether the player stumbled in here without a light
+    pub fn is_dark(&self) -> bool {
+        is_darkness(&format!("== {} ==", self.name)) || self.description.lines().any(is_darkness)
+    }
+
+    /// Whether the description warns that `exit` leads into the dark, like
+    /// "The east passage appears very dark; you feel likely to be eaten by a Grue."
+    pub fn warns_of_darkness(&self, exit: &str) -> bool {
+        self.description
+            .lines()
+            .filter(|line| line.to_lowercase().contains("grue"))
+            .flat_map(|line| line.split(['.', ';']))
+            .filter(|clause| clause.contains("dark"))
+            .any(|clause| clause.split_whitespace().any(|word| word == exit))
+    }
+
+    /// Exits that have not been taken yet
+    pub fn unexplored(&self) -> impl Iterator<Item = &str> {
+        self.exits
+            .iter()
+            .filter(|exit| !self.traversed.contains_key(*exit))
+            .map(String::as_str)
+    }
+}
+
+/// Rooms seen so far, keyed by fingerprint
+#[derive(Debug, Default)]
+pub struct Map {
+    rooms: HashMap<String, Room>,
+    current: Option<String>,
+    // the exit taken out of the current room, linked up once the next room shows up
+    leaving: Option<String>,
+    parsing: Option<Room>,
+    section: Section,
+    // (room, exit) pairs that killed the player, never explored again
+    dead_ends: HashSet<(String, String)>,
+    // (room, exit) pairs that went nowhere, until something opens them up
+    blocked: HashSet<(String, String)>,
+    // the room the player came from and the exit that led to the current one
+    entered: Option<(String, String)>,
+    // without one, dark rooms and exits warned to be dark are left alone
+    light: bool,
+    // how often each (room, exit) pair led right back into the same room
+    loops: HashMap<(String, String), u32>,
+}
+
+// a corridor of rooms that look alike can't be told from an exit looping back,
+// so such exits are tried a few more times before giving up on them
+const LOOP_RETRIES: u32 = 3;
+
+// which part of a room block the next list entry belongs to
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+enum Section {
+    #[default]
+    Description,
+    Things,
+    Exits,
+}
+
+impl Map {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Feeds one line of game output into the map
+    pub fn read_line(&mut self, line: &str) {
+        if let Some(name) = room_name(line) {
+            self.parsing = Some(Room {
+                name: name.to_string(),
+                ..Room::default()
+            });
+            self.section = Section::Description;
+            return;
+        }
+
+        if line == "What do you do?" {
+            self.finish_room();
+            return;
+        }
+
+        let Some(room) = &mut self.parsing else {
+            return;
+        };
+
+        if line == "Things of interest here:" {
+            self.section = Section::Things;
+            return;
+        }
+        if is_exits_header(line) {
+            self.section = Section::Exits;
+            return;
+        }
+
+        match (self.section, list_entry(line)) {
+            (Section::Things, Some(thing)) => room.things.push(thing),
+            (Section::Exits, Some(exit)) => room.exits.push(exit),
+            (Section::Description, _) if !line.is_empty() => {
+                if !room.description.is_empty() {
+                    room.description.push('\n');
+                }
+                room.description.push_str(line);
+            }
+            _ => {}
+        }
+    }
+
+    /// Records that `exit` is about to be taken out of the current room
+    pub fn leave(&mut self, exit: &str) {
+        self.leaving = Some(exit.to_string());
+    }
+
+    /// Marks the exit being taken, if any, as deadly so exploring avoids it,
+    /// then forgets the position like `forget_position`
+    pub fn record_death(&mut self) {
+        if let (Some(room), Some(exit)) = (self.current.clone(), self.leaving.clone()) {
+            self.dead_ends.insert((room, exit));
+        }
+        self.forget_position();
+    }
+
+    /// Picks the exit of the current room that undoes the move into it and records
+    /// leaving through it: one known to lead back, else the opposite direction,
+    /// or "back" if the way in isn't known. None if the room lists no such exit
+    pub fn retreat(&mut self) -> Option<String> {
+        let room = self.current()?;
+        let entered = self.entered.as_ref();
+        let opposite = entered.map_or("back", |(_, entrance)| opposite(entrance));
+        let exit = room
+            .exits
+            .iter()
+            .find(|exit| {
+                entered.is_some_and(|(previous, _)| room.traversed.get(*exit) == Some(previous))
+            })
+            .or_else(|| room.exits.iter().find(|exit| *exit == opposite))?
+            .clone();
+
+        self.leave(&exit);
+        Some(exit)
+    }
+
+    /// Tries exits that went nowhere again, for when something may have opened
+    /// them, like the coins unlocking the door of the ruins
+    pub fn unblock(&mut self) {
+        self.blocked.clear();
+    }
+
+    /// Tells exploring whether the player carries a light
+    pub fn set_light(&mut self, light: bool) {
+        self.light = light;
+    }
+
+    /// Forgets where the player is, keeping the rooms seen so far.
+    /// For when the game starts over
+    pub fn forget_position(&mut self) {
+        self.current = None;
+        self.leaving = None;
+        self.parsing = None;
+        self.entered = None;
+    }
+
+    pub fn rooms(&self) -> &HashMap<String, Room> {
+        &self.rooms
+    }
+
+    /// A room called `name`, any one of them if several share it
+    pub fn room(&self, name: &str) -> Option<&Room> {
+        self.rooms.values().find(|room| room.name == name)
+    }
+
+    /// The room the player was last seen in
+    pub fn current(&self) -> Option<&Room> {
+        self.rooms.get(self.current.as_ref()?)
+    }
+
+    /// Directions that lead from the current room to `target`, going only
+    /// through exits that have already been taken.
+    /// None if the target was never seen or can't be reached that way yet.
+    /// Of rooms sharing the name, the nearest one is picked
+    pub fn path_to(&self, target: &str) -> Option<Vec<String>> {
+        self.path_to_nearest(|room| room.name == target)
+    }
+
+    /// Exits of `room` that were never taken and aren't known to kill the player
+    /// or go nowhere. Without a light that leaves out anything dark, where a grue
+    /// gets the player before they find their way back.
+    /// Exits that led back into the same room come last, the fewer times the sooner
+    pub fn explorable<'a>(&'a self, room: &'a Room) -> impl Iterator<Item = &'a str> {
+        let too_dark = !self.light && room.is_dark();
+        let open = move |exit: &&str| {
+            !too_dark
+                && (self.light || !room.warns_of_darkness(exit))
+                && !self
+                    .dead_ends
+                    .contains(&(room.fingerprint(), exit.to_string()))
+                && !self
+                    .blocked
+                    .contains(&(room.fingerprint(), exit.to_string()))
+                && self.loops(room, exit) < LOOP_RETRIES
+        };
+        let mut tried: Vec<&str> = room
+            .unexplored()
+            .filter(open)
+            .filter(|exit| self.loops(room, exit) > 0)
+            .collect();
+        tried.sort_by_key(|exit| self.loops(room, exit));
+        room.unexplored()
+            .filter(open)
+            .filter(move |exit| self.loops(room, exit) == 0)
+            .chain(tried)
+    }
+
+    /// Takes one step toward exploring everything: an explorable exit of the current
+    /// room, or else the first exit on the way to the nearest room that has one.
+    /// Looks around first if the position isn't known.
+    /// Returns false, sending nothing, once no explorable exit can be reached
+    pub fn explore_step(&mut self, controller: &mut impl Controller) -> bool {
+        if self.current.is_none() {
+            controller.look();
+            return true;
+        }
+
+        // exits never taken before trying the ones that looped again
+        let fresh = |room: &Room| {
+            self.explorable(room)
+                .any(|exit| self.loops(room, exit) == 0)
+        };
+        let Some(path) = self
+            .path_to_nearest(fresh)
+            .or_else(|| self.path_to_nearest(|room| self.explorable(room).next().is_some()))
+        else {
+            return false;
+        };
+        let exit = match path.into_iter().next() {
+            Some(step) => step,
+            None => {
+                let Some(room) = self.current() else {
+                    return false;
+                };
+                // the game tends to list the way back last, taking it first finishes
+                // the rooms behind before crossing one way paths like the rope bridge
+                let fresh = self
+                    .explorable(room)
+                    .filter(|exit| self.loops(room, exit) == 0)
+                    .last();
+                match fresh.or_else(|| self.explorable(room).next()) {
+                    Some(exit) => exit.to_string(),
+                    None => return false,
+                }
+            }
+        };
+
+        self.leave(&exit);
+        controller.go_place(&exit);
+        true
+    }
+
+    /// Takes the first exit on the way to the nearest room `found` accepts.
+    /// Returns false, sending nothing, if there's no such room or the player is
+    /// already in one
+    pub fn step_toward(
+        &mut self,
+        controller: &mut impl Controller,
+        found: impl Fn(&Room) -> bool,
+    ) -> bool {
+        let Some(exit) = self
+            .path_to_nearest(found)
+            .and_then(|path| path.into_iter().next())
+        else {
+            return false;
+        };
+        self.leave(&exit);
+        controller.go_place(&exit);
+        true
+    }
+
+    fn loops(&self, room: &Room, exit: &str) -> u32 {
+        self.loops
+            .get(&(room.fingerprint(), exit.to_string()))
+            .copied()
+            .unwrap_or(0)
+    }
+
+    // breadth first over the exits that have been taken, so the path is a shortest one
+    fn path_to_nearest(&self, found: impl Fn(&Room) -> bool) -> Option<Vec<String>> {
+        let start = self.current.as_ref()?;
+
+        let mut came_from: HashMap<&str, (&str, &str)> = HashMap::new();
+        let mut seen: HashSet<&str> = HashSet::from([start.as_str()]);
+        let mut queue: VecDeque<&str> = VecDeque::from([start.as_str()]);
+        let target = loop {
+            let key = queue.pop_front()?;
+            let Some(room) = self.rooms.get(key) else {
+                continue;
+            };
+            if found(room) {
+                break key;
+            }
+            // sorted so ties always go the same way
+            let mut traversed: Vec<_> = room.traversed.iter().collect();
+            traversed.sort_unstable();
+            for (exit, next) in traversed {
+                if seen.insert(next) {
+                    came_from.insert(next, (key, exit));
+                    queue.push_back(next);
+                }
+            }
+        };
+
+        let mut path = vec![];
+        let mut key = target;
+        while key != start {
+            let (previous, exit) = came_from.get(key)?;
+            path.push(exit.to_string());
+            key = previous;
+        }
+        path.reverse();
+        Some(path)
+    }
+
+    fn finish_room(&mut self) {
+        let leaving = self.leaving.take();
+        let Some(mut room) = self.parsing.take() else {
+            // the move failed, trying it again won't get anywhere until something changes
+            if let (Some(exit), Some(current)) = (leaving, &self.current) {
+                self.blocked.insert((current.clone(), exit));
+            }
+            return;
+        };
+
+        // a move back to a room seen before links up with it, so loops in the maze
+        // count as explored and the exits not taken yet are the ones left to try
+        let fingerprint = room.fingerprint();
+        match (&leaving, &self.current) {
+            // the same room again, unless it's the next one of a corridor
+            (Some(exit), Some(previous)) if *previous == fingerprint => {
+                *self
+                    .loops
+                    .entry((fingerprint.clone(), exit.clone()))
+                    .or_default() += 1;
+            }
+            (Some(exit), Some(previous)) => {
+                if let Some(previous) = self.rooms.get_mut(previous) {
+                    previous.traversed.insert(exit.clone(), fingerprint.clone());
+                }
+            }
+            _ => {}
+        }
+
+        // looking around again keeps the way in, anything else that moves the player doesn't
+        self.entered = match (leaving, self.current.take()) {
+            (Some(exit), Some(previous)) => Some((previous, exit)),
+            (None, Some(previous)) if previous == fingerprint => self.entered.take(),
+            _ => None,
+        };
+
+        if let Some(known) = self.rooms.remove(&fingerprint) {
+            room.traversed = known.traversed;
+        }
+        self.current = Some(fingerprint.clone());
+        self.rooms.insert(fingerprint, room);
+    }
+}
+
+// the exit expected to lead back where `exit` came from, the game
+// mostly offers "back" where there's no opposite direction
+fn opposite(exit: &str) -> &str {
+    match exit {
+        "north" => "south",
+        "south" => "north",
+        "east" => "west",
+        "west" => "east",
+        "up" => "down",
+        "down" => "up",
+        "ladder" => "ladder",
+        _ => "back",
+    }
+}
+
+fn room_name(line: &str) -> Option<&str> {
+    line.strip_prefix("== ")?.strip_suffix(" ==")
+}
+
+// "There is 1 exit:" or "There are N exits:"
+fn is_exits_header(line: &str) -> bool {
+    line.starts_with("There ") && (line.ends_with(" exit:") || line.ends_with(" exits:"))
+}
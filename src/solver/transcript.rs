@@ -0,0 +1,88 @@
+use std::io::Write;
+
+use super::{Controller, Observer};
+
+/// Controller that writes the game's output and every command it sends into
+/// a log, in the order they happened, before passing the commands on
+pub struct Transcript<C: Controller, W: Write> {
+    controller: C,
+    log: W,
+    turn: u64,
+}
+
+impl<C: Controller, W: Write> Transcript<C, W> {
+    pub fn new(controller: C, log: W) -> Self {
+        Self {
+            controller,
+            log,
+            turn: 0,
+        }
+    }
+
+    /// Number of commands sent so far
+    pub fn turn(&self) -> u64 {
+        self.turn
+    }
+
+    pub fn into_inner(self) -> (C, W) {
+        (self.controller, self.log)
+    }
+
+    fn record(&mut self, command: &str) {
+        self.turn += 1;
+        let _ = writeln!(self.log, "[turn {}] > {command}", self.turn);
+    }
+}
+
+impl<C: Controller, W: Write> Controller for Transcript<C, W> {
+    fn help(&mut self) {
+        self.record("help");
+        self.controller.help();
+    }
+
+    fn look(&mut self) {
+        self.record("look");
+        self.controller.look();
+    }
+
+    fn inv(&mut self) {
+        self.record("inv");
+        self.controller.inv();
+    }
+
+    fn go_place(&mut self, place: &str) {
+        self.record(&format!("go {place}"));
+        self.controller.go_place(place);
+    }
+
+    fn take_thing(&mut self, thing: &str) {
+        self.record(&format!("take {thing}"));
+        self.controller.take_thing(thing);
+    }
+
+    fn drop_thing(&mut self, thing: &str) {
+        self.record(&format!("drop {thing}"));
+        self.controller.drop_thing(thing);
+    }
+
+    fn use_thing(&mut self, thing: &str) {
+        self.record(&format!("use {thing}"));
+        self.controller.use_thing(thing);
+    }
+
+    fn command(&mut self, command: &str) {
+        self.record(command);
+        self.controller.command(command);
+    }
+}
+
+impl<C: Controller, W: Write> Observer for Transcript<C, W> {
+    fn on_output(&mut self, line: &str) {
+        let _ = writeln!(self.log, "{line}");
+        self.controller.on_output(line);
+    }
+
+    fn on_death(&mut self, line: &str) -> Vec<String> {
+        self.controller.on_death(line)
+    }
+}
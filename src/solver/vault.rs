@@ -0,0 +1,105 @@
+use std::collections::{HashSet, VecDeque};
+
+use super::Controller;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VaultRoom {
+    Number(u16),
+    Add,
+    Sub,
+    Mul,
+}
+
+use VaultRoom::{Add, Mul, Number as N, Sub};
+
+/// The rooms in front of the vault, south row first and west to east.
+/// The orb starts in the south west corner, the vault door is north east
+pub const VAULT_GRID: [[VaultRoom; 4]; 4] = [
+    [N(22), Sub, N(9), Mul],
+    [Add, N(4), Sub, N(18)],
+    [N(4), Mul, N(11), Mul],
+    [Mul, N(8), Sub, N(1)],
+];
+
+pub const ORB_START: u16 = 22;
+pub const VAULT_WEIGHT: u16 = 30;
+
+// (row, column) of the antechamber and the vault door
+const START: (usize, usize) = (0, 0);
+const DOOR: (usize, usize) = (3, 3);
+
+// directions and how they move through the grid, north is up a row
+const MOVES: [(&str, isize, isize); 4] = [
+    ("north", 1, 0),
+    ("east", 0, 1),
+    ("south", -1, 0),
+    ("west", 0, -1),
+];
+
+// a room and the orb's weight after walking into it
+type OrbState = ((usize, usize), u16);
+
+// the orb shatters past this, so heavier states aren't worth searching
+const MAX_WEIGHT: u16 = (1 << 15) - 1;
+
+/// Finds the fewest moves that carry the orb from the antechamber to the vault
+/// door weighing exactly `VAULT_WEIGHT`. Walking into a number room applies the
+/// operator room walked through just before it. Going back into the antechamber
+/// resets the orb and the weight has to stay above 0, so neither is allowed
+pub fn solve_vault(grid: &[[VaultRoom; 4]; 4]) -> Option<Vec<&'static str>> {
+    // positions on a number room carry the weight, on an operator room the
+    // weight from before it, so (position, weight) is the whole state
+    let start = (START, ORB_START);
+    let mut seen: HashSet<OrbState> = HashSet::from([start]);
+    let mut queue: VecDeque<(OrbState, Vec<&'static str>)> = VecDeque::from([(start, vec![])]);
+
+    while let Some((((row, col), weight), path)) = queue.pop_front() {
+        for (direction, dy, dx) in MOVES {
+            let (Some(next_row), Some(next_col)) =
+                (row.checked_add_signed(dy), col.checked_add_signed(dx))
+            else {
+                continue;
+            };
+            if next_row >= 4 || next_col >= 4 || (next_row, next_col) == START {
+                continue;
+            }
+
+            let next_weight = match (grid[row][col], grid[next_row][next_col]) {
+                (Add, N(n)) => weight.checked_add(n),
+                (Sub, N(n)) => weight.checked_sub(n),
+                (Mul, N(n)) => weight.checked_mul(n),
+                _ => Some(weight),
+            };
+            let Some(next_weight @ 1..=MAX_WEIGHT) = next_weight else {
+                continue;
+            };
+
+            let mut next_path = path.clone();
+            next_path.push(direction);
+            if (next_row, next_col) == DOOR {
+                if next_weight == VAULT_WEIGHT {
+                    return Some(next_path);
+                }
+                // the door takes the orb unless it weighs just right
+                continue;
+            }
+
+            let state = ((next_row, next_col), next_weight);
+            if seen.insert(state) {
+                queue.push_back((state, next_path));
+            }
+        }
+    }
+
+    None
+}
+
+/// Walks the orb to the vault door along the shortest way there
+pub fn walk_vault(controller: &mut impl Controller) -> bool {
+    let Some(path) = solve_vault(&VAULT_GRID) else {
+        return false;
+    };
+    path.iter()
+        .for_each(|direction| controller.go_place(direction));
+    true
+}
@@ -1,436 +1,1457 @@
-use std::io::{self, PipeReader, PipeWriter, Read, Write};
+use std::collections::{HashSet, VecDeque};
+use std::io::{self, BufWriter, PipeReader, PipeWriter, Read, Write};
+use std::ops::Range;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver, Sender, TryRecvError};
+use std::sync::{Arc, Mutex};
+
+use serde::{Deserialize, Serialize};
+
+use crate::decode::{Instruction, Operand, decode_with};
+
+mod interpreter;
+
+use interpreter::{Io, Memory, Stack, execute};
 
 #[derive(Debug)]
-#[allow(dead_code)]
-enum Error {
-    InvalidAddress(u16),
-    InvalidUint15(u16),
-    InvalidRegister(u16),
+#[allow(clippy::enum_variant_names)]
+pub enum Error {
+    /// `at` is the byte offset of the operand the value was read from, if any
+    InvalidAddress {
+        value: u16,
+        at: Option<u16>,
+    },
+    InvalidUint15 {
+        value: u16,
+        at: Option<u16>,
+    },
+    InvalidRegister {
+        value: u16,
+        at: Option<u16>,
+    },
+    InvalidInstruction(u16),
     EmptyStack,
+    StackOverflow,
+    DivideByZero,
+    /// `op_add` or `op_mult` overflowed a `u16` before the modulo, see `check_arithmetic`
+    ArithmeticOverflow,
+    InputClosed,
+    InputExhausted,
+    ExecutingData(u16),
+    BudgetExceeded,
+    /// The flag from `interrupt_handle` was set
+    Interrupted,
+    HistoryEmpty,
+    /// A snapshot's RAM or registers don't have this machine's size
+    SnapshotMismatch,
     IOError(io::Error),
 }
 
 impl std::fmt::Display for Error {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
-            Error::InvalidAddress(addr) => write!(f, "Invalid Address: {}", addr),
-            Error::InvalidUint15(int) => write!(f, "Invalid Uint15: {}", int),
-            Error::InvalidRegister(reg) => write!(f, "Invalid Register: {}", reg),
+            Error::InvalidAddress { value, at } => {
+                write!(f, "Invalid Address: {}{}", value, Location(*at))
+            }
+            Error::InvalidUint15 { value, at } => {
+                write!(f, "Invalid Uint15: {}{}", value, Location(*at))
+            }
+            Error::InvalidRegister { value, at } => {
+                write!(f, "Invalid Register: {}{}", value, Location(*at))
+            }
+            Error::InvalidInstruction(op) => write!(f, "Invalid Instruction: {}", op),
             Error::EmptyStack => write!(f, "Empty Stack"),
-            Error::IOError(_) => write!(f, "IO Error"),
+            Error::StackOverflow => write!(f, "Stack Overflow"),
+            Error::DivideByZero => write!(f, "Divide By Zero"),
+            Error::ArithmeticOverflow => write!(f, "Arithmetic Overflow"),
+            Error::InputClosed => write!(f, "Input Closed"),
+            Error::InputExhausted => write!(f, "Input Exhausted"),
+            Error::ExecutingData(pc) => write!(f, "Executing Data: {}", pc),
+            Error::BudgetExceeded => write!(f, "Budget Exceeded"),
+            Error::Interrupted => write!(f, "Interrupted"),
+            Error::HistoryEmpty => write!(f, "History Empty"),
+            Error::SnapshotMismatch => write!(f, "Snapshot Mismatch"),
+            Error::IOError(err) => write!(f, "IO Error: {}", err),
+        }
+    }
+}
+
+// where a bad operand was read from, if it came from memory
+struct Location(Option<u16>);
+
+impl std::fmt::Display for Location {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.0 {
+            Some(at) => write!(f, " at {at:#06x}"),
+            None => Ok(()),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::IOError(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Opcode {
+    Halt,
+    Set,
+    Push,
+    Pop,
+    Eq,
+    Gt,
+    Jmp,
+    Jt,
+    Jf,
+    Add,
+    Mult,
+    Mod,
+    And,
+    Or,
+    Not,
+    Rmem,
+    Wmem,
+    Call,
+    Ret,
+    Out,
+    In,
+    Noop,
+}
+
+impl TryFrom<u16> for Opcode {
+    type Error = Error;
+
+    fn try_from(value: u16) -> Result<Self, Self::Error> {
+        let opcode = match value {
+            0 => Opcode::Halt,
+            1 => Opcode::Set,
+            2 => Opcode::Push,
+            3 => Opcode::Pop,
+            4 => Opcode::Eq,
+            5 => Opcode::Gt,
+            6 => Opcode::Jmp,
+            7 => Opcode::Jt,
+            8 => Opcode::Jf,
+            9 => Opcode::Add,
+            10 => Opcode::Mult,
+            11 => Opcode::Mod,
+            12 => Opcode::And,
+            13 => Opcode::Or,
+            14 => Opcode::Not,
+            15 => Opcode::Rmem,
+            16 => Opcode::Wmem,
+            17 => Opcode::Call,
+            18 => Opcode::Ret,
+            19 => Opcode::Out,
+            20 => Opcode::In,
+            21 => Opcode::Noop,
+            v => return Err(Error::InvalidInstruction(v)),
+        };
+
+        Ok(opcode)
+    }
+}
+
+impl Opcode {
+    pub fn mnemonic(self) -> &'static str {
+        match self {
+            Opcode::Halt => "halt",
+            Opcode::Set => "set",
+            Opcode::Push => "push",
+            Opcode::Pop => "pop",
+            Opcode::Eq => "eq",
+            Opcode::Gt => "gt",
+            Opcode::Jmp => "jmp",
+            Opcode::Jt => "jt",
+            Opcode::Jf => "jf",
+            Opcode::Add => "add",
+            Opcode::Mult => "mult",
+            Opcode::Mod => "mod",
+            Opcode::And => "and",
+            Opcode::Or => "or",
+            Opcode::Not => "not",
+            Opcode::Rmem => "rmem",
+            Opcode::Wmem => "wmem",
+            Opcode::Call => "call",
+            Opcode::Ret => "ret",
+            Opcode::Out => "out",
+            Opcode::In => "in",
+            Opcode::Noop => "noop",
         }
     }
+
+    /// Number of operand words following the opcode word
+    pub fn operand_count(self) -> u16 {
+        match self {
+            Opcode::Halt | Opcode::Ret | Opcode::Noop => 0,
+            Opcode::Push | Opcode::Pop | Opcode::Jmp | Opcode::Call | Opcode::Out | Opcode::In => 1,
+            Opcode::Set | Opcode::Jt | Opcode::Jf | Opcode::Not | Opcode::Rmem | Opcode::Wmem => 2,
+            Opcode::Eq
+            | Opcode::Gt
+            | Opcode::Add
+            | Opcode::Mult
+            | Opcode::Mod
+            | Opcode::And
+            | Opcode::Or => 3,
+        }
+    }
+
+    /// Number of words the instruction takes up, its opcode included
+    pub fn length(self) -> u16 {
+        self.operand_count() + 1
+    }
+}
+
+/// Number of words an instruction starting with the word `opcode` takes up,
+/// `None` if it isn't an opcode. The interpreter and the decoder both go by this
+pub fn instruction_length(opcode: u16) -> Option<u16> {
+    Opcode::try_from(opcode).ok().map(Opcode::length)
 }
 
-const REGISTER_COUNT: u16 = 8;
-const WORD_BITS: u8 = 15;
+// the architecture of the challenge, which `State` runs
+pub(crate) const WORD_BITS: u8 = 15;
+pub(crate) const REGISTER_COUNT: usize = 8;
 
-const ADDRESS_SPACE: u16 = !(1 << WORD_BITS);
-const RAM_SIZE: usize = 1 << WORD_BITS + 1;
-const REGISTER_SPACE: u16 = ADDRESS_SPACE + REGISTER_COUNT;
-const REGISTER_1: u16 = ADDRESS_SPACE + 1;
-const INVALID_START: u16 = ADDRESS_SPACE + REGISTER_COUNT + 1;
+pub(crate) const REGISTER_1: u16 = register_1(WORD_BITS);
 const MIN_STACK_SIZE: usize = 1 << 8;
+const OPCODE_COUNT: usize = 22;
+
+// highest word address and highest literal value
+pub(crate) const fn address_space(word_bits: u8) -> u16 {
+    (1 << word_bits) - 1
+}
+
+// values past the address space name the registers, starting with r0
+pub(crate) const fn register_1(word_bits: u8) -> u16 {
+    address_space(word_bits) + 1
+}
+
+// the value naming the last register
+pub(crate) const fn register_space(word_bits: u8, register_count: usize) -> u16 {
+    address_space(word_bits) + register_count as u16
+}
+
+// RAM holds 2^WORD_BITS words of two bytes each
+const fn ram_size(word_bits: u8) -> usize {
+    (1 << word_bits) * 2
+}
+
+/// Address of a word, as the program's jump targets, `op_call` return addresses
+/// and `rmem`/`wmem` operands are given
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct WordAddr(pub u16);
+
+/// Offset of a byte in RAM, as the program counter, breakpoints and watchpoints are given
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct ByteAddr(pub u16);
+
+impl WordAddr {
+    pub fn to_byte(self) -> ByteAddr {
+        ByteAddr(self.0 << 1)
+    }
+}
+
+impl ByteAddr {
+    /// The word this byte is part of
+    pub fn to_word(self) -> WordAddr {
+        WordAddr(self.0 >> 1)
+    }
+
+    // where the instruction after an `opcode` starting here begins
+    pub(crate) fn past(self, opcode: Opcode) -> ByteAddr {
+        self + opcode.length() * 2
+    }
+}
+
+// moves on by a number of bytes
+impl std::ops::Add<u16> for ByteAddr {
+    type Output = ByteAddr;
 
-type Registers = [u16; REGISTER_COUNT as usize];
-type Stack = [u16];
-type RAM = [u8; RAM_SIZE];
+    fn add(self, bytes: u16) -> ByteAddr {
+        ByteAddr(self.0 + bytes)
+    }
+}
+
+type OutputHook = Box<dyn FnMut(u8) + Send>;
+
+/// A write by `op_wmem` to a watched address or to code that already ran.
+/// `addr` and `pc` are byte offsets, `old` and `new` the whole word
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WatchHit {
+    pub addr: u16,
+    pub old: u16,
+    pub new: u16,
+    pub pc: u16,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RunOutcome {
+    Halted,
+    Limit,
+    /// `op_in` found no data, only reported for input that can signal `WouldBlock`
+    NeedInput,
+}
+
+/// Sent to a `State` from another thread while it's inside `run_for`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DebugCmd {
+    Pause,
+    Resume,
+    /// Pauses when the pc reaches the byte offset
+    SetBreakpoint(u16),
+    RemoveBreakpoint(u16),
+    ReadReg(u8),
+}
 
-struct Memory<'a> {
-    registers: &'a mut Registers,
-    stack: &'a mut Stack,
-    ram: &'a mut RAM,
+/// What the `State` answers each `DebugCmd` with
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DebugResp {
+    /// Stopped before the instruction at the byte offset, also sent on breakpoints
+    Paused(u16),
+    Resumed,
+    Done,
+    Register(Option<u16>),
 }
 
+/// Why the program stopped
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum HaltReason {
+    Running,
+    /// Ran `op_halt`, a clean exit
+    Halt,
+    /// `op_ret` found the stack empty
+    RetEmptyStack,
+    /// Stopped on an error, with its message
+    Error(String),
+}
+
+/// Everything mutable about a running program, except for the pipes
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Snapshot {
+    program_ptr: u16,
+    registers: Box<[u16]>,
+    stack: Vec<u16>,
+    ram: Box<[u8]>,
+    // `Running` unless the program had halted, errors are not part of the state
+    halt_reason: HaltReason,
+}
+
+// what save_to persists, the binary is kept so reset still works after loading
+#[derive(Serialize, Deserialize)]
+struct SaveFile {
+    bin: Box<[u8]>,
+    snapshot: Snapshot,
+}
+
+/// Configures a `State` before it starts running.
+/// Seeded registers only apply to the initial state, `reset` still zeroes them
+pub struct StateBuilder<'a, const WORD_BITS: u8, const REGISTER_COUNT: usize> {
+    bin: &'a [u8],
+    stack_size: usize,
+    // (index, value) in the order they were given, checked by build
+    registers: Vec<(u8, u16)>,
+}
+
+impl<const WORD_BITS: u8, const REGISTER_COUNT: usize> StateBuilder<'_, WORD_BITS, REGISTER_COUNT> {
+    /// Initial stack capacity, the stack still grows as needed
+    pub fn stack_size(mut self, stack_size: usize) -> Self {
+        self.stack_size = stack_size;
+        self
+    }
+
+    /// Seeds register `idx` with `value`, building fails if either is invalid
+    pub fn register(mut self, idx: u8, value: u16) -> Self {
+        self.registers.push((idx, value));
+        self
+    }
+
+    pub fn build(
+        self,
+    ) -> Result<(Machine<WORD_BITS, REGISTER_COUNT>, (PipeReader, PipeWriter)), Error> {
+        let (stdout_reader, stdout) = io::pipe().map_err(Error::IOError)?;
+        let (stdin, stdin_writer) = io::pipe().map_err(Error::IOError)?;
+
+        let state = self.build_with_io(stdin, stdout)?;
+
+        Ok((state, (stdout_reader, stdin_writer)))
+    }
+
+    /// Builds a `State` reading from `stdin` and writing to `stdout` instead of internal pipes
+    pub fn build_with_io(
+        self,
+        stdin: impl Read + Send + 'static,
+        stdout: impl Write + Send + 'static,
+    ) -> Result<Machine<WORD_BITS, REGISTER_COUNT>, Error> {
+        let mut state = Machine::new(self.bin, self.stack_size, Box::new(stdin), Box::new(stdout));
+
+        for (idx, value) in self.registers {
+            state.set_register(idx, value)?;
+        }
+
+        Ok(state)
+    }
+}
+
+/// The challenge's VM
+pub type State = Machine<WORD_BITS, REGISTER_COUNT>;
+
+/// A VM with `WORD_BITS` bit words, up to 15, and `REGISTER_COUNT` registers.
+/// Other sizes than `State`'s are for experimenting with the architecture
 // #[derive(Debug)]
 #[allow(unused)]
-pub struct State {
+pub struct Machine<const WORD_BITS: u8, const REGISTER_COUNT: usize> {
     bin: Box<[u8]>,
 
     program_ptr: u16,
-    registers: Registers,
-    stack: Box<Stack>,
-    ram: RAM,
-
-    stdout: io::PipeWriter,
-    stdin: io::PipeReader,
+    cycles: u64,
+    opcode_counts: [u64; OPCODE_COUNT],
+    coverage: Box<[bool]>,
+    memory: Memory<WORD_BITS, REGISTER_COUNT>,
+    breakpoints: HashSet<u16>,
+    watchpoints: HashSet<u16>,
+    trace: Option<Box<dyn Write + Send>>,
+    trace_range: Option<Range<u16>>,
+    history: VecDeque<Snapshot>,
+    history_depth: usize,
+    detect_code_writes: bool,
+    code_writes: Vec<WatchHit>,
+    detect_data_execution: bool,
+    checked_arithmetic: bool,
+    // op_wmem writes per word, only allocated while profiling
+    write_counts: Option<Box<[u32]>>,
+    instruction_budget: Option<u64>,
+    halt_reason: HaltReason,
+    debugger: Option<(Receiver<DebugCmd>, Sender<DebugResp>)>,
+    paused: bool,
+    waiting_for_input: Arc<AtomicBool>,
+    interrupt: Arc<AtomicBool>,
+    output_hook: Option<OutputHook>,
+    // lines from feed_line, read before anything from stdin
+    queued_input: VecDeque<u8>,
+
+    stdout: BufWriter<Box<dyn Write + Send>>,
+    stdin: Box<dyn Read + Send>,
 }
 
-impl State {
-    pub fn init_with(bin: &[u8]) -> (Self, (PipeReader, PipeWriter)) {
-        let mut ram = [0; RAM_SIZE];
+/// Copies registers, stack, RAM, the binary and the debugging state.
+/// The clone is not connected to anything: it reads as if its input was closed
+/// and its output is discarded. Use `State::fork` to get pipes for it
+impl<const WORD_BITS: u8, const REGISTER_COUNT: usize> Clone
+    for Machine<WORD_BITS, REGISTER_COUNT>
+{
+    fn clone(&self) -> Self {
+        self.clone_with_io(Box::new(io::empty()), Box::new(io::sink()))
+    }
+}
 
-        for i in 0..bin.len() {
-            ram[i] = bin[i]
-        }
+impl<const WORD_BITS: u8, const REGISTER_COUNT: usize> Machine<WORD_BITS, REGISTER_COUNT> {
+    const ADDRESS_SPACE: u16 = address_space(WORD_BITS);
+    const RAM_SIZE: usize = ram_size(WORD_BITS);
 
+    /// Creates a `State` connected to fresh pipes, returning the ends for
+    /// reading its output and writing its input.
+    /// Panics if `bin` is bigger than the VM's RAM
+    pub fn init_with(bin: &[u8]) -> (Self, (PipeReader, PipeWriter)) {
         let (stdout_reader, stdout) = io::pipe().expect("Should be able to create pipe");
         let (stdin, stdin_writer) = io::pipe().expect("Should be able to create pipe");
 
-        let state = Self {
-            program_ptr: 0,
-            registers: [0; REGISTER_COUNT as usize],
-            bin: boxed_copy(bin),
-            stack: boxed_slice(MIN_STACK_SIZE),
-            ram,
-            stdout,
-            stdin,
-        };
+        let state = Self::with_io(bin, stdin, stdout);
 
         (state, (stdout_reader, stdin_writer))
     }
 
-    #[allow(unused)]
+    /// Creates a `State` that reads from `stdin` and writes to `stdout` directly,
+    /// e.g. a `Cursor` for deterministic tests.
+    /// Panics if `bin` is bigger than the VM's RAM
+    pub fn with_io(
+        bin: &[u8],
+        stdin: impl Read + Send + 'static,
+        stdout: impl Write + Send + 'static,
+    ) -> Self {
+        Self::new(bin, MIN_STACK_SIZE, Box::new(stdin), Box::new(stdout))
+    }
+
+    pub fn builder(bin: &[u8]) -> StateBuilder<'_, WORD_BITS, REGISTER_COUNT> {
+        StateBuilder {
+            bin,
+            stack_size: MIN_STACK_SIZE,
+            registers: vec![],
+        }
+    }
+
+    fn new(
+        bin: &[u8],
+        stack_size: usize,
+        stdin: Box<dyn Read + Send>,
+        stdout: Box<dyn Write + Send>,
+    ) -> Self {
+        assert!(
+            bin.len() <= Self::RAM_SIZE,
+            "Binary of {} bytes does not fit into {} bytes of RAM",
+            bin.len(),
+            Self::RAM_SIZE
+        );
+
+        let mut ram = vec![0; Self::RAM_SIZE].into_boxed_slice();
+        ram[..bin.len()].copy_from_slice(bin);
+
+        Self {
+            program_ptr: 0,
+            cycles: 0,
+            opcode_counts: [0; OPCODE_COUNT],
+            coverage: vec![false; Self::RAM_SIZE].into_boxed_slice(),
+            bin: bin.to_vec().into_boxed_slice(),
+            memory: Memory {
+                registers: [0; REGISTER_COUNT],
+                stack: Stack::with_capacity(stack_size),
+                ram,
+                last_write: (0, 0, 0),
+                halted: false,
+            },
+            breakpoints: HashSet::new(),
+            watchpoints: HashSet::new(),
+            trace: None,
+            trace_range: None,
+            history: VecDeque::new(),
+            history_depth: 0,
+            detect_code_writes: false,
+            code_writes: vec![],
+            detect_data_execution: false,
+            checked_arithmetic: false,
+            write_counts: None,
+            instruction_budget: None,
+            halt_reason: HaltReason::Running,
+            debugger: None,
+            paused: false,
+            waiting_for_input: Arc::new(AtomicBool::new(false)),
+            interrupt: Arc::new(AtomicBool::new(false)),
+            output_hook: None,
+            queued_input: VecDeque::new(),
+            stdout: BufWriter::new(stdout),
+            stdin,
+        }
+    }
+
     pub fn reset(&mut self) {
+        self.reset_registers();
+        self.cycles = 0;
+        self.opcode_counts = [0; OPCODE_COUNT];
+        self.coverage.fill(false);
+        if let Some(counts) = &mut self.write_counts {
+            counts.fill(0);
+        }
+        self.history.clear();
+
+        for i in 0..self.memory.ram.len() {
+            self.memory.ram[i] = *self.bin.get(i).unwrap_or(&0);
+        }
+    }
+
+    /// Byte offsets of every place `needle` occurs in RAM, overlapping ones included.
+    /// Text the program prints is usually stored a word per character, so
+    /// look for e.g. `b"F\0o\0o\0"` rather than `b"Foo"`
+    pub fn find_bytes(&self, needle: &[u8]) -> Vec<u16> {
+        if needle.is_empty() {
+            return vec![];
+        }
+
+        self.memory
+            .ram
+            .windows(needle.len())
+            .enumerate()
+            .filter(|(_, window)| *window == needle)
+            .map(|(offset, _)| offset as u16)
+            .collect()
+    }
+
+    /// Writes a hex dump of the bytes in `start..end` of RAM, 16 to a line with
+    /// their byte offset and the printable ones as ASCII. `end` is clamped to the end of RAM
+    pub fn dump_memory(&self, start: u16, end: u16, out: &mut impl Write) -> io::Result<()> {
+        let end = (end as usize).min(Self::RAM_SIZE);
+        let start = (start as usize).min(end);
+
+        for (line, bytes) in self.memory.ram[start..end].chunks(16).enumerate() {
+            let hex: String = bytes.iter().map(|byte| format!("{byte:02x} ")).collect();
+            let ascii: String = bytes
+                .iter()
+                .map(|&byte| match byte.is_ascii_graphic() || byte == b' ' {
+                    true => byte as char,
+                    false => '.',
+                })
+                .collect();
+            writeln!(out, "{:#06x}  {hex:<48}|{ascii}|", start + line * 16)?;
+        }
+
+        Ok(())
+    }
+
+    /// Calls `hook` with every byte `op_out` prints, in addition to writing it to stdout.
+    /// The hook gets each byte as soon as it is printed, stdout only once it is
+    /// flushed before the next `op_in` or when the program halts
+    pub fn set_output_hook(&mut self, hook: Box<dyn FnMut(u8) + Send>) {
+        self.output_hook = Some(hook);
+    }
+
+    pub fn clear_output_hook(&mut self) {
+        self.output_hook = None;
+    }
+
+    /// Flag that is set while `op_in` waits for input, so another thread
+    /// can tell the program is blocked on input rather than still running
+    pub fn waiting_for_input(&self) -> Arc<AtomicBool> {
+        self.waiting_for_input.clone()
+    }
+
+    /// Flag another thread, or a Ctrl-C handler, can set to make `step` stop with
+    /// `Interrupted` before the next instruction. The output is flushed first and
+    /// the flag cleared, so running again carries on where it stopped
+    pub fn interrupt_handle(&self) -> Arc<AtomicBool> {
+        self.interrupt.clone()
+    }
+
+    /// Queues `line` and a newline as input. `op_in` reads queued input
+    /// before it reads from stdin
+    pub fn feed_line(&mut self, line: &str) {
+        self.queued_input.extend(line.bytes());
+        self.queued_input.push_back(b'\n');
+    }
+
+    /// Whether the next instruction is an `op_in`, which blocks until input arrives
+    pub fn peek_next_is_input(&self) -> bool {
+        !self.done()
+            && matches!(
+                decode_with::<WORD_BITS, REGISTER_COUNT>(&self.memory.ram, self.program_ptr >> 1),
+                Ok((Instruction::In { .. }, _))
+            )
+    }
+
+    /// Starts the program over from the current RAM, keeping any changes made
+    /// to it. Only the program counter, registers, stack and halt reason are reset
+    pub fn reset_registers(&mut self) {
         self.program_ptr = 0;
+        self.halt_reason = HaltReason::Running;
+        self.memory.registers = [0; REGISTER_COUNT];
+        self.memory.stack = Stack::with_capacity(MIN_STACK_SIZE);
+    }
+
+    /// Byte offset into RAM of the next instruction to execute.
+    /// This is twice the word address used by the binary's jump targets
+    pub fn program_counter(&self) -> u16 {
+        self.program_ptr
+    }
+
+    /// Number of instructions executed since the last reset
+    pub fn cycles(&self) -> u64 {
+        self.cycles
+    }
+
+    /// Number of times each opcode was executed since the last reset, indexed by opcode
+    pub fn opcode_counts(&self) -> [u64; OPCODE_COUNT] {
+        self.opcode_counts
+    }
+
+    /// Values on the stack, the top of the stack last.
+    /// Return addresses pushed by `op_call` are word addresses
+    pub fn stack(&self) -> &[u16] {
+        &self.memory.stack.values
+    }
+
+    /// The whole memory image, two little-endian bytes per word.
+    /// Compare it against the binary to see what the program wrote
+    pub fn ram(&self) -> &[u8] {
+        &self.memory.ram
+    }
 
-        for i in 0..self.registers.len() {
-            self.registers[i] = 0;
+    /// Marks every byte offset an executed instruction started at since the last reset
+    pub fn coverage(&self) -> &[bool] {
+        &self.coverage
+    }
+
+    pub fn register(&self, idx: u8) -> Option<u16> {
+        self.memory.registers.get(idx as usize).copied()
+    }
+
+    pub fn set_register(&mut self, idx: u8, value: u16) -> Result<(), Error> {
+        let Some(register) = self.memory.registers.get_mut(idx as usize) else {
+            return Err(Error::InvalidRegister {
+                value: idx as u16,
+                at: None,
+            });
+        };
+
+        if value > Self::ADDRESS_SPACE {
+            return Err(Error::InvalidUint15 { value, at: None });
+        }
+
+        *register = value;
+        Ok(())
+    }
+
+    /// Reads the raw word at word address `addr`, without resolving registers
+    pub fn read_word(&self, addr: u16) -> Result<u16, Error> {
+        if addr > Self::ADDRESS_SPACE {
+            return Err(Error::InvalidAddress {
+                value: addr,
+                at: None,
+            });
         }
 
-        self.stack = boxed_slice(MIN_STACK_SIZE);
+        let ptr = (addr << 1) as usize;
+        Ok(u16::from_le_bytes([
+            self.memory.ram[ptr],
+            self.memory.ram[ptr + 1],
+        ]))
+    }
 
-        for i in 0..self.ram.len() {
-            self.ram[i] = *self.bin.get(i).unwrap_or(&0);
+    /// Writes the raw word `value` to word address `addr`.
+    /// `value` is not validated so operands referring to registers can be patched in
+    pub fn write_word(&mut self, addr: u16, value: u16) -> Result<(), Error> {
+        if addr > Self::ADDRESS_SPACE {
+            return Err(Error::InvalidAddress {
+                value: addr,
+                at: None,
+            });
         }
+
+        let ptr = (addr << 1) as usize;
+        let [byte1, byte2] = value.to_le_bytes();
+        self.memory.ram[ptr] = byte1;
+        self.memory.ram[ptr + 1] = byte2;
+        Ok(())
     }
 
+    /// Writes `words` to consecutive word addresses starting at `addr`, like
+    /// `write_word` does. Nothing is written unless the whole patch fits
+    pub fn apply_patch(&mut self, addr: u16, words: &[u16]) -> Result<(), Error> {
+        // the last word of the patch, reported if it's out of range
+        let last = (addr as usize + words.len()).saturating_sub(1);
+        if last > Self::ADDRESS_SPACE as usize {
+            return Err(Error::InvalidAddress {
+                value: last.min(u16::MAX as usize) as u16,
+                at: None,
+            });
+        }
+
+        for (addr, &word) in (addr..).zip(words) {
+            self.write_word(addr, word)?;
+        }
+        Ok(())
+    }
+
+    /// Whether the program halted, through `op_halt` or `op_ret` on an empty stack
     pub fn done(&self) -> bool {
-        self.program_ptr == REGISTER_1
+        matches!(
+            self.halt_reason,
+            HaltReason::Halt | HaltReason::RetEmptyStack
+        )
     }
 
-    pub fn next(&mut self) {
-        let program_ptr @ 0..REGISTER_1 = self.program_ptr else {
-            return;
-        };
+    /// Why the program stopped, recorded when it halts or a step fails
+    pub fn halt_reason(&self) -> &HaltReason {
+        &self.halt_reason
+    }
+
+    #[allow(clippy::should_implement_trait)]
+    pub fn next(&mut self) -> Result<(), Error> {
+        self.step().map(|_| ())
+    }
+
+    /// Executes a single instruction and returns which one it was.
+    /// Once the program has halted this keeps returning `Opcode::Halt`
+    pub fn step(&mut self) -> Result<Opcode, Error> {
+        let result = self.execute_next();
+        match &result {
+            // only means there's no input yet, see run_for
+            Err(Error::IOError(err)) if err.kind() == io::ErrorKind::WouldBlock => {}
+            // a pause, the program can carry on
+            Err(Error::Interrupted) => {}
+            Err(err) => self.halt_reason = HaltReason::Error(err.to_string()),
+            Ok(_) => {}
+        }
+        result
+    }
+
+    /// Steps until the program halts, yielding the byte offset and opcode of
+    /// every instruction executed. An error is yielded once and ends the iterator
+    pub fn run_iter(&mut self) -> impl Iterator<Item = Result<(u16, Opcode), Error>> {
+        let mut failed = false;
+        std::iter::from_fn(move || {
+            if failed || self.done() {
+                return None;
+            }
+
+            let pc = self.program_ptr;
+            let result = self.step().map(|opcode| (pc, opcode));
+            failed = result.is_err();
+            Some(result)
+        })
+    }
 
-        let stack_ptr = self.stack[0];
+    fn execute_next(&mut self) -> Result<Opcode, Error> {
+        let program_ptr = self.program_ptr;
+        if self.done() {
+            return Ok(Opcode::Halt);
+        }
 
-        if stack_ptr as usize == (self.stack.len() - 2) / 2 {
-            self.expand_stack();
-        } else if stack_ptr as usize <= (self.stack.len() - 2) / 4
-            && stack_ptr as usize > MIN_STACK_SIZE
+        if self
+            .instruction_budget
+            .is_some_and(|max| self.cycles >= max)
         {
-            self.shrink_stack();
+            return Err(Error::BudgetExceeded);
         }
 
-        let mut memory = Memory {
-            registers: &mut self.registers,
-            stack: &mut self.stack,
-            ram: &mut self.ram,
-        };
+        // falling through past the last word, only possible with smaller word sizes
+        if program_ptr as usize + 1 >= self.memory.ram.len() {
+            return Err(Error::InvalidAddress {
+                value: ByteAddr(program_ptr).to_word().0,
+                at: None,
+            });
+        }
+
+        if self.interrupt.load(Ordering::Relaxed) {
+            self.interrupt.store(false, Ordering::Relaxed);
+            self.stdout.flush().map_err(Error::IOError)?;
+            return Err(Error::Interrupted);
+        }
+
+        if self.trace.is_some()
+            && self
+                .trace_range
+                .as_ref()
+                .is_none_or(|range| range.contains(&program_ptr))
+        {
+            self.write_trace(program_ptr)?;
+        }
+
+        // anything the disassembler would list as `.word` isn't an instruction
+        if self.detect_data_execution
+            && decode_with::<WORD_BITS, REGISTER_COUNT>(&self.memory.ram, program_ptr >> 1).is_err()
+        {
+            return Err(Error::ExecutingData(program_ptr));
+        }
+
+        self.coverage[program_ptr as usize] = true;
+
+        let before = (self.history_depth > 0).then(|| self.snapshot());
+
+        let memory = &mut self.memory;
 
-        let result = match memory.ram[program_ptr as usize] {
-            0 => op_halt(), //halt
-            1 => op_set(program_ptr, &mut memory),
-            2 => op_push(program_ptr, &mut memory),
-            3 => op_pop(program_ptr, &mut memory),
-            4 => op_eq(program_ptr, &mut memory),
-            5 => op_gt(program_ptr, &mut memory),
-            6 => op_jmp(program_ptr, &mut memory),
-            7 => op_jt(program_ptr, &mut memory),
-            8 => op_jf(program_ptr, &mut memory),
-            9 => op_add(program_ptr, &mut memory),
-            10 => op_mult(program_ptr, &mut memory),
-            11 => op_mod(program_ptr, &mut memory),
-            12 => op_and(program_ptr, &mut memory),
-            13 => op_or(program_ptr, &mut memory),
-            14 => op_not(program_ptr, &mut memory),
-            15 => op_rmem(program_ptr, &mut memory),
-            16 => op_wmem(program_ptr, &mut memory),
-            17 => op_call(program_ptr, &mut memory),
-            18 => op_ret(program_ptr, &mut memory),
-            19 => op_out(program_ptr, &mut memory, &mut self.stdout),
-            20 => op_in(program_ptr, &mut memory, &mut self.stdin),
-            21 => op_noop(program_ptr, &mut memory), // no-op
-            v @ _ => panic!("Invalid instruction: {:02X} at {:02X}", v, program_ptr),
+        let opcode = Opcode::try_from(u16::from_le_bytes([
+            memory.ram[program_ptr as usize],
+            memory.ram[program_ptr as usize + 1],
+        ]))?;
+
+        // a prompt printed right before reading input has to reach the reader
+        if opcode == Opcode::In {
+            self.stdout.flush().map_err(Error::IOError)?;
+        }
+
+        let mut io = PipeIo {
+            queued: &mut self.queued_input,
+            stdin: &mut self.stdin,
+            stdout: &mut self.stdout,
+            hook: &mut self.output_hook,
+            waiting_for_input: &self.waiting_for_input,
         };
+        self.program_ptr = execute(
+            opcode,
+            ByteAddr(program_ptr),
+            memory,
+            &mut io,
+            self.checked_arithmetic,
+        )?
+        .0;
+
+        if std::mem::take(&mut self.memory.halted) {
+            // op_ret halts the program as well when there's nothing to return to
+            self.halt_reason = match opcode {
+                Opcode::Ret => HaltReason::RetEmptyStack,
+                _ => HaltReason::Halt,
+            };
+            self.stdout.flush().map_err(Error::IOError)?;
+        }
 
-        match result {
-            Ok(new_pointer) => self.program_ptr = new_pointer,
-            Err(err) => panic!("{}", err),
+        self.cycles += 1;
+        self.opcode_counts[opcode as usize] += 1;
+
+        if let Some(counts) = &mut self.write_counts
+            && opcode == Opcode::Wmem
+        {
+            let count = &mut counts[self.memory.last_write.0 as usize >> 1];
+            *count = count.saturating_add(1);
         }
+
+        if self.detect_code_writes && opcode == Opcode::Wmem {
+            let (addr, old, new) = self.memory.last_write;
+            if self.executed(addr) {
+                self.code_writes.push(WatchHit {
+                    addr,
+                    old,
+                    new,
+                    pc: program_ptr,
+                });
+            }
+        }
+
+        if let Some(before) = before {
+            if self.history.len() == self.history_depth {
+                self.history.pop_front();
+            }
+            self.history.push_back(before);
+        }
+
+        Ok(opcode)
     }
 
-    fn expand_stack(&mut self) {
-        resize_boxed_slice(self.stack.len() * 2, &mut self.stack);
+    /// Records every `op_wmem` that overwrites part of an instruction that
+    /// has already been executed, to find code that rewrites itself
+    pub fn detect_code_writes(&mut self, enabled: bool) {
+        self.detect_code_writes = enabled;
     }
 
-    fn shrink_stack(&mut self) {
-        resize_boxed_slice(self.stack.len() / 2, &mut self.stack);
+    /// Makes `step` fail with `BudgetExceeded` once `max` instructions ran
+    /// without halting. Counts the same instructions as `cycles`, so `reset` starts over
+    pub fn set_instruction_budget(&mut self, max: u64) {
+        self.instruction_budget = Some(max);
     }
-}
 
-fn resize_boxed_slice<T: Copy>(new_size: usize, to_resize: &mut Box<[T]>) {
-    unsafe {
-        let bytes_to_copy = std::cmp::min(new_size, to_resize.len());
-        let mut new_slice = boxed_slice(new_size);
-        std::ptr::copy_nonoverlapping(to_resize.as_ptr(), new_slice.as_mut_ptr(), bytes_to_copy);
-        *to_resize = new_slice;
+    pub fn clear_instruction_budget(&mut self) {
+        self.instruction_budget = None;
     }
-}
 
-fn boxed_slice<T>(size: usize) -> Box<[T]> {
-    unsafe {
-        let Ok(layout) = std::alloc::Layout::array::<T>(size) else {
-            panic!("Could not create array layout")
+    /// Makes `step` fail with `ExecutingData` instead of running a word that
+    /// doesn't decode to a whole instruction, like the text a bad jump lands in
+    pub fn detect_data_execution(&mut self, enabled: bool) {
+        self.detect_data_execution = enabled;
+    }
+
+    /// Makes `op_add` and `op_mult` fail with `ArithmeticOverflow` if their result
+    /// overflows a `u16` before the modulo is taken, instead of trusting the masked math.
+    /// That finds the programs relying on a plain `u16` wrap, like `mult` with a
+    /// product above 65535
+    pub fn check_arithmetic(&mut self, enabled: bool) {
+        self.checked_arithmetic = enabled;
+    }
+
+    /// Counts the `op_wmem` writes to every word, see `write_count`.
+    /// Disabling it drops the counts
+    pub fn enable_write_profiling(&mut self, enabled: bool) {
+        self.write_counts = match enabled {
+            true => self
+                .write_counts
+                .take()
+                .or_else(|| Some(vec![0; Self::RAM_SIZE / 2].into_boxed_slice())),
+            false => None,
         };
-        let ptr = std::alloc::alloc(layout) as *mut T;
-        std::ptr::write_bytes(ptr, 0, size);
-        let slice: *mut [T] = std::ptr::slice_from_raw_parts_mut(ptr, size);
-        Box::from_raw(slice)
     }
-}
 
-fn boxed_copy<T: Copy>(to_copy: &[T]) -> Box<[T]> {
-    unsafe {
-        let layout = std::alloc::Layout::for_value(to_copy);
-        let ptr = std::alloc::alloc(layout) as *mut T;
-        std::ptr::copy_nonoverlapping(to_copy.as_ptr(), ptr, to_copy.len());
-        let slice: *mut [T] = std::ptr::slice_from_raw_parts_mut(ptr, to_copy.len());
-        Box::from_raw(slice)
+    /// How often `op_wmem` wrote the word at byte offset `addr` since profiling
+    /// was enabled or the last reset. Always 0 while profiling is off or for an
+    /// address past the end of RAM
+    pub fn write_count(&self, addr: u16) -> u32 {
+        self.write_counts.as_ref().map_or(0, |counts| {
+            counts.get(addr as usize >> 1).copied().unwrap_or(0)
+        })
     }
-}
 
-//   halt: 0
-//   stop execution and terminate the program
-fn op_halt() -> Result<u16, Error> {
-    Ok(REGISTER_1)
-}
+    /// Writes to executed code recorded since the last call
+    pub fn take_code_writes(&mut self) -> Vec<WatchHit> {
+        std::mem::take(&mut self.code_writes)
+    }
 
-//   1 a b
-//   set register <a> to the value of <b>
-fn op_set(ptr: u16, memory: &mut Memory) -> Result<u16, Error> {
-    let register = read_register(ptr + 2, memory)?;
-    let value = read_uint15(ptr + 4, memory)?;
-    memory.registers[register] = value;
-    Ok(ptr + 6)
-}
+    // whether the word at byte offset addr belongs to an instruction that has run,
+    // either as its opcode or as one of the operands following it
+    fn executed(&self, addr: u16) -> bool {
+        let addr = addr as usize;
+        self.coverage[addr]
+            || (1..=3).any(|operand| {
+                let Some(start) = addr.checked_sub(operand * 2) else {
+                    return false;
+                };
+                let word = u16::from_le_bytes([self.memory.ram[start], self.memory.ram[start + 1]]);
+                self.coverage[start]
+                    && Opcode::try_from(word)
+                        .is_ok_and(|opcode| opcode.operand_count() as usize >= operand)
+            })
+    }
 
-//   2 a
-//   push <a> onto the stack
-fn op_push(ptr: u16, memory: &mut Memory) -> Result<u16, Error> {
-    let a = read_uint15(ptr + 2, memory)?;
+    /// Keeps a snapshot from before each of the last `depth` instructions so
+    /// they can be undone with `step_back`. Every snapshot holds a copy of RAM
+    pub fn enable_history(&mut self, depth: usize) {
+        self.history_depth = depth;
+        while self.history.len() > depth {
+            self.history.pop_front();
+        }
+    }
 
-    let [stack_ptr, stack @ ..] = memory.stack else {
-        unreachable!()
-    };
+    pub fn disable_history(&mut self) {
+        self.enable_history(0);
+    }
 
-    stack[*stack_ptr as usize] = a;
-    *stack_ptr += 1;
+    /// Undoes the last instruction recorded in the history.
+    /// Registers, stack, RAM and the program counter go back, counters and output don't
+    pub fn step_back(&mut self) -> Result<(), Error> {
+        let snapshot = self.history.pop_back().ok_or(Error::HistoryEmpty)?;
+        self.restore(&snapshot)
+    }
 
-    Ok(ptr + 4)
-}
+    /// Writes one line per executed instruction to `writer`, with its byte offset,
+    /// the decoded instruction and the values of the registers it references
+    pub fn enable_trace(&mut self, writer: Box<dyn Write + Send>) {
+        self.trace = Some(writer);
+    }
 
-//   3 a
-//   remove the top element from the stack and write it into <a>; empty stack = error
-fn op_pop(ptr: u16, memory: &mut Memory) -> Result<u16, Error> {
-    let register = read_register(ptr + 2, memory)?;
+    pub fn disable_trace(&mut self) {
+        self.trace = None;
+    }
 
-    let Memory {
-        registers,
-        stack: [stack_ptr, stack @ ..],
-        ram: _,
-    } = memory
-    else {
-        unreachable!()
-    };
+    /// Only traces instructions at byte offsets in `start..end`
+    pub fn set_trace_range(&mut self, start: u16, end: u16) {
+        self.trace_range = Some(start..end);
+    }
 
-    if *stack_ptr == 0 {
-        return Err(Error::EmptyStack);
+    pub fn clear_trace_range(&mut self) {
+        self.trace_range = None;
     }
 
-    *stack_ptr -= 1;
-    registers[register] = stack[*stack_ptr as usize];
+    fn write_trace(&mut self, program_ptr: u16) -> Result<(), Error> {
+        let Some(trace) = &mut self.trace else {
+            return Ok(());
+        };
 
-    Ok(ptr + 4)
-}
+        let line =
+            match decode_with::<WORD_BITS, REGISTER_COUNT>(&self.memory.ram, program_ptr >> 1) {
+                Ok((instruction, _)) => {
+                    let registers: Vec<String> = instruction
+                        .operands()
+                        .iter()
+                        .filter_map(|operand| match operand {
+                            Operand::Register(r) => {
+                                Some(format!("r{r}={:#06x}", self.memory.registers[*r as usize]))
+                            }
+                            Operand::Literal(_) => None,
+                        })
+                        .collect();
+
+                    match registers.is_empty() {
+                        true => format!("{program_ptr:#06x}  {instruction}"),
+                        false => format!(
+                            "{program_ptr:#06x}  {instruction}  [{}]",
+                            registers.join(" ")
+                        ),
+                    }
+                }
+                Err(err) => format!("{program_ptr:#06x}  <{err}>"),
+            };
+
+        writeln!(trace, "{line}").map_err(Error::IOError)
+    }
 
-//   4 a b c
-//   set <a> to 1 if <b> is equal to <c>; set it to 0 otherwise
-fn op_eq(ptr: u16, memory: &mut Memory) -> Result<u16, Error> {
-    let register = read_register(ptr + 2, memory)?;
-    let a = read_uint15(ptr + 4, memory)?;
-    let b = read_uint15(ptr + 6, memory)?;
+    /// Whether the program counter, registers, stack and RAM match `other`'s,
+    /// the pipes, counters and debugging state aside
+    pub fn state_eq(&self, other: &Self) -> bool {
+        self.program_ptr == other.program_ptr
+            && self.done() == other.done()
+            && self.memory.registers == other.memory.registers
+            && self.memory.stack.values == other.memory.stack.values
+            && self.memory.ram == other.memory.ram
+    }
 
-    memory.registers[register] = (a == b) as u16;
+    /// What `state_eq` would find different, one line each with this state's
+    /// value first. RAM is compared word by word
+    pub fn state_diff(&self, other: &Self) -> Vec<String> {
+        let mut diff = vec![];
+        if self.program_ptr != other.program_ptr {
+            diff.push(format!(
+                "pc: {:#06x} != {:#06x}",
+                self.program_ptr, other.program_ptr
+            ));
+        }
+        if self.done() != other.done() {
+            diff.push(format!("done: {} != {}", self.done(), other.done()));
+        }
+        let registers = self.memory.registers.iter().zip(&other.memory.registers);
+        for (idx, (a, b)) in registers.enumerate() {
+            if a != b {
+                diff.push(format!("r{idx}: {a:#06x} != {b:#06x}"));
+            }
+        }
+        if self.memory.stack.values != other.memory.stack.values {
+            diff.push(format!(
+                "stack: {:?} != {:?}",
+                self.memory.stack.values, other.memory.stack.values
+            ));
+        }
+        let words = self.memory.ram.chunks(2).zip(other.memory.ram.chunks(2));
+        for (addr, (a, b)) in words.enumerate() {
+            if a != b {
+                let a = u16::from_le_bytes([a[0], a[1]]);
+                let b = u16::from_le_bytes([b[0], b[1]]);
+                diff.push(format!("ram {:#06x}: {a:#06x} != {b:#06x}", addr * 2));
+            }
+        }
+        diff
+    }
 
-    Ok(ptr + 8)
-}
+    pub fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            program_ptr: self.program_ptr,
+            registers: Box::from(self.memory.registers),
+            stack: self.memory.stack.values.clone(),
+            ram: self.memory.ram.to_vec().into_boxed_slice(),
+            halt_reason: match self.done() {
+                true => self.halt_reason.clone(),
+                false => HaltReason::Running,
+            },
+        }
+    }
 
-//   5 a b c
-//   set <a> to 1 if <b> is greater than <c>; set it to 0 otherwise
-fn op_gt(ptr: u16, memory: &mut Memory) -> Result<u16, Error> {
-    let register = read_register(ptr + 2, memory)?;
-    let a = read_uint15(ptr + 4, memory)?;
-    let b = read_uint15(ptr + 6, memory)?;
+    /// Goes back to `snap`, or fails with `SnapshotMismatch`, changing nothing,
+    /// if it was taken from a machine of another size
+    pub fn restore(&mut self, snap: &Snapshot) -> Result<(), Error> {
+        if snap.ram.len() != Self::RAM_SIZE || snap.registers.len() != REGISTER_COUNT {
+            return Err(Error::SnapshotMismatch);
+        }
 
-    memory.registers[register] = (a > b) as u16;
+        self.program_ptr = snap.program_ptr;
+        self.halt_reason = snap.halt_reason.clone();
+        self.memory.registers.copy_from_slice(&snap.registers);
+        self.memory.ram.copy_from_slice(&snap.ram);
 
-    Ok(ptr + 8)
-}
+        self.memory.stack = Stack {
+            values: snap.stack.clone(),
+        };
+        Ok(())
+    }
 
-//   6 a
-//   jump to <a>
-fn op_jmp(ptr: u16, memory: &mut Memory) -> Result<u16, Error> {
-    Ok(read_uint15_address(ptr + 2, memory)?)
-}
+    pub fn save_to(&self, path: &Path) -> io::Result<()> {
+        let save = SaveFile {
+            bin: self.bin.clone(),
+            snapshot: self.snapshot(),
+        };
 
-//   7 a b
-//   if <a> is nonzero, jump to <b>
-fn op_jt(ptr: u16, memory: &mut Memory) -> Result<u16, Error> {
-    let new_ptr = match read_uint15(ptr + 2, memory)? {
-        1..=u16::MAX => read_uint15_address(ptr + 4, memory)?,
-        0 => ptr + 6,
-    };
+        let bytes = bincode::serialize(&save).map_err(io::Error::other)?;
+        std::fs::write(path, bytes)
+    }
 
-    Ok(new_ptr)
-}
+    /// Loads a state written by `save_to`, connected to fresh pipes like `init_with`
+    pub fn load_from(path: &Path) -> io::Result<(Self, (PipeReader, PipeWriter))> {
+        let bytes = std::fs::read(path)?;
+        let save: SaveFile = bincode::deserialize(&bytes)
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+
+        let (mut state, pipes) = Self::init_with(&save.bin);
+        state
+            .restore(&save.snapshot)
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+        Ok((state, pipes))
+    }
 
-//   8 a b
-//   if <a> is zero, jump to <b>
-fn op_jf(ptr: u16, memory: &mut Memory) -> Result<u16, Error> {
-    let new_ptr = match read_uint15(ptr + 2, memory)? {
-        0 => read_uint15_address(ptr + 4, memory)?,
-        1..=u16::MAX => ptr + 6,
-    };
+    /// Clones the VM at its current point and connects the copy to fresh pipes like `init_with`,
+    /// so each branch of a decision can be run and the one that works kept
+    pub fn fork(&self) -> (Self, (PipeReader, PipeWriter)) {
+        let (stdout_reader, stdout) = io::pipe().expect("Should be able to create pipe");
+        let (stdin, stdin_writer) = io::pipe().expect("Should be able to create pipe");
 
-    Ok(new_ptr)
-}
+        let state = self.clone_with_io(Box::new(stdin), Box::new(stdout));
 
-macro_rules! operator_operation {
-    ($($ident:ident with ($($operand:ident),*) is ($($exp:tt)*))*) => ($(
-        fn $ident(ptr: u16, memory: &mut Memory) -> Result<u16, Error> {
-            let register = read_register(ptr + 2, memory)?;
-            let mut offset = 2;
+        (state, (stdout_reader, stdin_writer))
+    }
 
-            $(
-                offset += 2;
-                let $operand = read_uint15(ptr+offset, memory)?;
-            )*
+    // the trace writer, output hook and debugger belong to whoever set them up,
+    // so the copy starts without them
+    fn clone_with_io(&self, stdin: Box<dyn Read + Send>, stdout: Box<dyn Write + Send>) -> Self {
+        Self {
+            bin: self.bin.clone(),
+            program_ptr: self.program_ptr,
+            cycles: self.cycles,
+            opcode_counts: self.opcode_counts,
+            coverage: self.coverage.clone(),
+            memory: self.memory.clone(),
+            breakpoints: self.breakpoints.clone(),
+            watchpoints: self.watchpoints.clone(),
+            trace: None,
+            trace_range: self.trace_range.clone(),
+            history: self.history.clone(),
+            history_depth: self.history_depth,
+            detect_code_writes: self.detect_code_writes,
+            code_writes: self.code_writes.clone(),
+            detect_data_execution: self.detect_data_execution,
+            checked_arithmetic: self.checked_arithmetic,
+            write_counts: self.write_counts.clone(),
+            instruction_budget: self.instruction_budget,
+            halt_reason: self.halt_reason.clone(),
+            debugger: None,
+            paused: false,
+            waiting_for_input: Arc::new(AtomicBool::new(false)),
+            interrupt: Arc::new(AtomicBool::new(false)),
+            output_hook: None,
+            queued_input: self.queued_input.clone(),
+            stdout: BufWriter::new(stdout),
+            stdin,
+        }
+    }
 
-            memory.registers[register] = $($exp)*;
+    /// Executes up to `max_instructions` before yielding back to the caller.
+    /// An `op_in` whose input reports `WouldBlock` yields `NeedInput` and is retried on the next run,
+    /// while a closed input is still an `InputClosed` error
+    pub fn run_for(&mut self, max_instructions: u64) -> Result<RunOutcome, Error> {
+        for _ in 0..max_instructions {
+            if self.debugger.is_some() {
+                self.serve_debugger();
+            }
+
+            if self.done() {
+                return Ok(RunOutcome::Halted);
+            }
+
+            match self.step() {
+                Ok(_) => {}
+                Err(Error::IOError(err)) if err.kind() == io::ErrorKind::WouldBlock => {
+                    return Ok(RunOutcome::NeedInput);
+                }
+                Err(err) => return Err(err),
+            }
+
+            if let Some((_, responses)) = &self.debugger
+                && self.breakpoints.contains(&self.program_ptr)
+            {
+                self.paused = true;
+                let _ = responses.send(DebugResp::Paused(self.program_ptr));
+            }
+        }
 
-            Ok(ptr + offset + 2)
+        match self.done() {
+            true => Ok(RunOutcome::Halted),
+            false => Ok(RunOutcome::Limit),
         }
-    )*)
-}
+    }
 
-operator_operation! {
-    op_add  with (a, b) is ((a + b) % REGISTER_1)
-    op_mult with (a, b) is (a.wrapping_mul(b) % REGISTER_1)
-    op_mod  with (a, b) is (a % b)
-    op_and  with (a, b) is (a & b)
-    op_or   with (a, b) is (a | b)
-    op_not  with (a)    is (!a & ADDRESS_SPACE)
-}
+    /// Lets another thread control the program while it's inside `run_for`.
+    /// Commands are answered between instructions, while paused `run_for` waits
+    /// for the next one. Dropping the sender detaches the debugger again
+    pub fn attach_debugger(&mut self) -> (Sender<DebugCmd>, Receiver<DebugResp>) {
+        let (command_sender, commands) = mpsc::channel();
+        let (responses, response_receiver) = mpsc::channel();
+        self.debugger = Some((commands, responses));
+        (command_sender, response_receiver)
+    }
 
-//   15 a b
-//   read memory at address <b> and write it to <a>
-fn op_rmem(ptr: u16, memory: &mut Memory) -> Result<u16, Error> {
-    let register = read_register(ptr + 2, memory)?;
-    let addr = read_uint15_address(ptr + 4, memory)?;
-    let value = read_uint15(addr, memory)?;
+    // answers every pending command, and keeps waiting for more while paused
+    fn serve_debugger(&mut self) {
+        let Some((commands, responses)) = self.debugger.take() else {
+            return;
+        };
 
-    memory.registers[register] = value;
+        loop {
+            let command = match self.paused {
+                true => commands.recv().map_err(|_| TryRecvError::Disconnected),
+                false => commands.try_recv(),
+            };
+            let response = match command {
+                Ok(DebugCmd::Pause) => {
+                    self.paused = true;
+                    DebugResp::Paused(self.program_ptr)
+                }
+                Ok(DebugCmd::Resume) => {
+                    self.paused = false;
+                    DebugResp::Resumed
+                }
+                Ok(DebugCmd::SetBreakpoint(addr)) => {
+                    self.add_breakpoint(addr);
+                    DebugResp::Done
+                }
+                Ok(DebugCmd::RemoveBreakpoint(addr)) => {
+                    self.remove_breakpoint(addr);
+                    DebugResp::Done
+                }
+                Ok(DebugCmd::ReadReg(idx)) => DebugResp::Register(self.register(idx)),
+                Err(TryRecvError::Empty) => break,
+                // nobody left to resume, so carry on without a debugger
+                Err(TryRecvError::Disconnected) => {
+                    self.paused = false;
+                    return;
+                }
+            };
+            let _ = responses.send(response);
+        }
 
-    Ok(ptr + 6)
-}
+        self.debugger = Some((commands, responses));
+    }
 
-//   16 a b
-//   write the value from <b> into memory at address <a>
-fn op_wmem(ptr: u16, memory: &mut Memory) -> Result<u16, Error> {
-    let addr = read_uint15_address(ptr + 2, &memory)?;
-    let [byte1, byte2] = read_uint15(ptr + 4, memory)?.to_le_bytes();
+    /// Runs until the program halts with `input` as its input and returns
+    /// everything it printed. Asking for more input than given is an
+    /// `InputExhausted` error. The state's own input and output are left alone
+    pub fn run_with_input(&mut self, input: &[u8]) -> Result<Vec<u8>, Error> {
+        let output = CollectedOutput::default();
+        let stdin = std::mem::replace(&mut self.stdin, Box::new(io::Cursor::new(input.to_vec())));
+        let stdout = std::mem::replace(&mut self.stdout, BufWriter::new(Box::new(output.clone())));
+
+        let result = loop {
+            if self.done() {
+                break self.stdout.flush().map_err(Error::IOError);
+            }
+            match self.step() {
+                Ok(_) => {}
+                Err(Error::InputClosed) => break Err(Error::InputExhausted),
+                Err(err) => break Err(err),
+            }
+        };
 
-    memory.ram[addr as usize] = byte1;
-    memory.ram[addr as usize + 1] = byte2;
+        self.stdin = stdin;
+        self.stdout = stdout;
+        result
+            .map(|()| std::mem::take(&mut *output.0.lock().expect("Should be able to lock output")))
+    }
 
-    Ok(ptr + 6)
-}
+    /// Breakpoints are byte offsets, the same space as `program_counter`
+    pub fn add_breakpoint(&mut self, addr: u16) {
+        self.breakpoints.insert(addr);
+    }
+
+    pub fn remove_breakpoint(&mut self, addr: u16) {
+        self.breakpoints.remove(&addr);
+    }
 
-//   17 a
-//   write the address of the next instruction to the stack and jump to <a>
-fn op_call(ptr: u16, memory: &mut Memory) -> Result<u16, Error> {
-    let [stack_ptr, stack @ ..] = memory.stack else {
-        unreachable!()
-    };
+    /// Runs until the next instruction is on a breakpoint and returns its address,
+    /// without running that instruction, or `None` once the program halts.
+    /// Stopped on a breakpoint this returns right away, see `resume_from_breakpoint`
+    pub fn run_until_breakpoint(&mut self) -> Result<Option<u16>, Error> {
+        loop {
+            if self.done() {
+                return Ok(None);
+            }
 
-    stack[*stack_ptr as usize] = (ptr >> 1) + 2;
-    *stack_ptr += 1;
+            if self.breakpoints.contains(&self.program_ptr) {
+                return Ok(Some(self.program_ptr));
+            }
 
-    let addr = read_uint15_address(ptr + 2, &memory)?;
-    Ok(addr)
-}
+            self.step()?;
+        }
+    }
 
-//   18
-//   remove the top element from the stack and jump to it; empty stack = halt
-fn op_ret(_: u16, memory: &mut Memory) -> Result<u16, Error> {
-    let [stack_ptr, stack @ ..] = memory.stack else {
-        unreachable!()
-    };
+    /// Like `run_until_breakpoint`, but the current instruction always runs first
+    /// so a breakpoint on it doesn't stop the program again
+    pub fn resume_from_breakpoint(&mut self) -> Result<Option<u16>, Error> {
+        if self.done() {
+            return Ok(None);
+        }
 
-    if *stack_ptr == 0 {
-        return Ok(REGISTER_1);
+        self.step()?;
+        self.run_until_breakpoint()
     }
 
-    *stack_ptr -= 1;
-    let addr = stack[*stack_ptr as usize] << 1;
+    /// Runs a whole `call` and stops once it has returned, like a single step for
+    /// any other instruction. A breakpoint inside the subroutine still stops it early
+    pub fn step_over(&mut self) -> Result<(), Error> {
+        let Ok((Instruction::Call { .. }, _)) =
+            decode_with::<WORD_BITS, REGISTER_COUNT>(&self.memory.ram, self.program_ptr >> 1)
+        else {
+            return self.next();
+        };
 
-    Ok(addr)
-}
+        // call returns to the word after its operand, breakpoints want a byte offset
+        let return_ptr = ((self.program_ptr >> 1) + 2) << 1;
+        let depth = self.memory.stack.values.len();
+        let temporary = self.breakpoints.insert(return_ptr);
+
+        let result = loop {
+            match self.resume_from_breakpoint() {
+                // a recursive call passed the same address deeper down
+                Ok(Some(ptr)) if ptr == return_ptr && self.memory.stack.values.len() > depth => {}
+                Ok(_) => break Ok(()),
+                Err(err) => break Err(err),
+            }
+        };
 
-//   19 a
-//   write the character represented by ascii code <a> to the terminal
-fn op_out(ptr: u16, memory: &mut Memory, stdout: &mut PipeWriter) -> Result<u16, Error> {
-    let char = read_uint15(ptr + 2, memory)? as u8;
-    stdout.write(&[char]).map_err(|e| Error::IOError(e))?;
-    Ok(ptr + 4)
-}
+        if temporary {
+            self.breakpoints.remove(&return_ptr);
+        }
+        result
+    }
 
-//   20 a
-//   read a character from the terminal and write its ascii code to <a>; it can be assumed that once input starts, it will continue until a newline is encountered; this means that you can safely read whole lines from the keyboard instead of having to figure out how to read individual characters
-fn op_in(ptr: u16, memory: &mut Memory, stdin: &mut PipeReader) -> Result<u16, Error> {
-    let mut buf: [u8; 1] = [0];
-    stdin.read(&mut buf).map_err(|e| Error::IOError(e))?;
-    // io::stdin().read(&mut buf).map_err(|e| Error::IOError(e))?;
+    /// Watchpoints are byte offsets of the written word, like breakpoints
+    pub fn add_watchpoint(&mut self, addr: u16) {
+        self.watchpoints.insert(addr);
+    }
 
-    let register = read_register(ptr + 2, memory)?;
-    memory.registers[register] = u16::from_le_bytes([buf[0], 0]);
+    pub fn remove_watchpoint(&mut self, addr: u16) {
+        self.watchpoints.remove(&addr);
+    }
 
-    Ok(ptr + 4)
-}
+    /// Runs until `op_wmem` writes to a watched word, or returns `None` once the program halts
+    pub fn run_until_watchpoint(&mut self) -> Result<Option<WatchHit>, Error> {
+        while !self.done() {
+            let pc = self.program_ptr;
 
-fn op_noop(ptr: u16, _: &mut Memory) -> Result<u16, Error> {
-    Ok(ptr + 2)
-}
+            if self.step()? != Opcode::Wmem {
+                continue;
+            }
 
-fn read_uint15(ptr: u16, memory: &Memory) -> Result<u16, Error> {
-    let uint15 = u16::from_le_bytes([memory.ram[ptr as usize], memory.ram[ptr as usize + 1]]);
+            let (addr, old, new) = self.memory.last_write;
+            if self.watchpoints.contains(&addr) {
+                return Ok(Some(WatchHit { addr, old, new, pc }));
+            }
+        }
 
-    match uint15 {
-        0..=ADDRESS_SPACE => Ok(uint15),
-        REGISTER_1..=REGISTER_SPACE => Ok(memory.registers[(uint15 - REGISTER_1) as usize]),
-        INVALID_START..=u16::MAX => Err(Error::InvalidUint15(uint15)),
+        Ok(None)
     }
 }
 
-fn read_register(ptr: u16, memory: &Memory) -> Result<usize, Error> {
-    let uint15 = u16::from_le_bytes([memory.ram[ptr as usize], memory.ram[ptr as usize + 1]]);
+// hooks the interpreter up to the state's pipes for a single instruction
+struct PipeIo<'a> {
+    queued: &'a mut VecDeque<u8>,
+    stdin: &'a mut Box<dyn Read + Send>,
+    stdout: &'a mut BufWriter<Box<dyn Write + Send>>,
+    hook: &'a mut Option<OutputHook>,
+    waiting_for_input: &'a AtomicBool,
+}
 
-    match uint15 {
-        0..=ADDRESS_SPACE => Err(Error::InvalidRegister(uint15)),
-        REGISTER_1..=REGISTER_SPACE => Ok((uint15 - REGISTER_1) as usize),
-        INVALID_START..=u16::MAX => Err(Error::InvalidUint15(uint15)),
+impl Io for PipeIo<'_> {
+    fn read_byte(&mut self) -> Result<Option<u8>, Error> {
+        if let Some(byte) = self.queued.pop_front() {
+            return Ok(Some(byte));
+        }
+
+        let mut buf: [u8; 1] = [0];
+        self.waiting_for_input.store(true, Ordering::Release);
+        let read = self.stdin.read(&mut buf);
+        self.waiting_for_input.store(false, Ordering::Release);
+
+        match read.map_err(Error::IOError)? {
+            0 => Ok(None),
+            _ => Ok(Some(buf[0])),
+        }
+    }
+
+    fn write_byte(&mut self, byte: u8) -> Result<(), Error> {
+        if let Some(hook) = self.hook {
+            hook(byte);
+        }
+        self.stdout.write(&[byte]).map_err(Error::IOError)?;
+        Ok(())
     }
 }
 
-fn read_uint15_address(ptr: u16, memory: &Memory) -> Result<u16, Error> {
-    let uint15 = u16::from_le_bytes([memory.ram[ptr as usize], memory.ram[ptr as usize + 1]]);
+// collects the output of run_with_input while the state owns the writer
+#[derive(Clone, Default)]
+struct CollectedOutput(Arc<Mutex<Vec<u8>>>);
+
+impl Write for CollectedOutput {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0
+            .lock()
+            .expect("Should be able to lock output")
+            .write(buf)
+    }
 
-    match uint15 {
-        0..=ADDRESS_SPACE => Ok(uint15 << 1),
-        REGISTER_1..=REGISTER_SPACE => Ok(memory.registers[(uint15 - REGISTER_1) as usize] << 1),
-        INVALID_START..=u16::MAX => Err(Error::InvalidAddress(uint15)),
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
     }
 }
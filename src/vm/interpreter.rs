@@ -0,0 +1,395 @@
+// Registers, RAM, the stack and the opcodes themselves. Nothing in here
+// touches `std::io`, input and output go through `Io` so the interpreter
+// can run wherever something can hand it bytes
+
+use super::{
+    ByteAddr, Error, OPCODE_COUNT, Opcode, WordAddr, address_space, register_1, register_space,
+};
+
+/// Where `op_in` reads from and `op_out` writes to
+pub trait Io {
+    /// The next byte of input, `None` once there is none left
+    fn read_byte(&mut self) -> Result<Option<u8>, Error>;
+
+    fn write_byte(&mut self, byte: u8) -> Result<(), Error>;
+}
+
+/// Runs the instruction `opcode` at `ptr` and returns where the next one starts.
+/// `checked_arithmetic` swaps in the versions of `op_add` and `op_mult` that fail on overflow
+pub(super) fn execute<const WORD_BITS: u8, const REGISTER_COUNT: usize>(
+    opcode: Opcode,
+    ptr: ByteAddr,
+    memory: &mut Memory<WORD_BITS, REGISTER_COUNT>,
+    io: &mut impl Io,
+    checked_arithmetic: bool,
+) -> Result<ByteAddr, Error> {
+    match opcode {
+        Opcode::Out => memory.op_out(ptr, io),
+        Opcode::In => memory.op_in(ptr, io),
+        Opcode::Add if checked_arithmetic => memory.op_add_checked(ptr),
+        Opcode::Mult if checked_arithmetic => memory.op_mult_checked(ptr),
+        opcode => Memory::DISPATCH_TABLE[opcode as usize](memory, ptr),
+    }
+}
+
+type Operation<const WORD_BITS: u8, const REGISTER_COUNT: usize> =
+    fn(&mut Memory<WORD_BITS, REGISTER_COUNT>, ByteAddr) -> Result<ByteAddr, Error>;
+
+pub(super) type Registers<const REGISTER_COUNT: usize> = [u16; REGISTER_COUNT];
+
+// capped so a runaway recursion fails instead of exhausting memory
+const MAX_STACK_SIZE: usize = u16::MAX as usize;
+
+// the stack pointer is values.len(), capacity is managed by the Vec
+// so a push can never outrun it and a pop never drops live entries
+#[derive(Debug, Clone)]
+pub(super) struct Stack {
+    pub(super) values: Vec<u16>,
+}
+
+impl Stack {
+    pub(super) fn with_capacity(capacity: usize) -> Self {
+        Self {
+            values: Vec::with_capacity(capacity),
+        }
+    }
+
+    fn push(&mut self, value: u16) -> Result<(), Error> {
+        if self.values.len() >= MAX_STACK_SIZE {
+            return Err(Error::StackOverflow);
+        }
+
+        self.values.push(value);
+        Ok(())
+    }
+
+    fn pop(&mut self) -> Result<u16, Error> {
+        self.values.pop().ok_or(Error::EmptyStack)
+    }
+}
+
+// owned by State so the opcodes can borrow it directly on every instruction
+#[derive(Clone)]
+pub(super) struct Memory<const WORD_BITS: u8, const REGISTER_COUNT: usize> {
+    pub(super) registers: Registers<REGISTER_COUNT>,
+    pub(super) stack: Stack,
+    // 2^WORD_BITS words of two bytes each
+    pub(super) ram: Box<[u8]>,
+    // (address, old, new) of the most recent op_wmem
+    pub(super) last_write: (u16, u16, u16),
+    // set by op_halt and by op_ret on an empty stack, the program counter stays
+    // on the instruction so every address remains a valid jump target
+    pub(super) halted: bool,
+}
+
+// what an operand word stands for
+enum Value {
+    Literal(u16),
+    Register(usize),
+    Invalid,
+}
+
+macro_rules! operator_operation {
+    ($($ident:ident with ($($operand:ident),*) is ($($exp:tt)*))*) => ($(
+        fn $ident(&mut self, ptr: ByteAddr) -> Result<ByteAddr, Error> {
+            let register = self.read_register(ptr + 2)?;
+            let mut offset = 2;
+
+            $(
+                offset += 2;
+                let $operand = self.read_uint15(ptr+offset)?;
+            )*
+
+            self.registers[register] = $($exp)*;
+
+            Ok(ptr + offset + 2)
+        }
+    )*)
+}
+
+impl<const WORD_BITS: u8, const REGISTER_COUNT: usize> Memory<WORD_BITS, REGISTER_COUNT> {
+    // highest word address and highest literal value
+    const ADDRESS_SPACE: u16 = address_space(WORD_BITS);
+    const REGISTER_1: u16 = register_1(WORD_BITS);
+    const REGISTER_SPACE: u16 = register_space(WORD_BITS, REGISTER_COUNT);
+
+    // op_out and op_in need the I/O and are dispatched separately
+    const DISPATCH_TABLE: [Operation<WORD_BITS, REGISTER_COUNT>; OPCODE_COUNT] = [
+        Self::op_halt,
+        Self::op_set,
+        Self::op_push,
+        Self::op_pop,
+        Self::op_eq,
+        Self::op_gt,
+        Self::op_jmp,
+        Self::op_jt,
+        Self::op_jf,
+        Self::op_add,
+        Self::op_mult,
+        Self::op_mod,
+        Self::op_and,
+        Self::op_or,
+        Self::op_not,
+        Self::op_rmem,
+        Self::op_wmem,
+        Self::op_call,
+        Self::op_ret,
+        Self::op_invalid_io,
+        Self::op_invalid_io,
+        Self::op_noop,
+    ];
+
+    //   halt: 0
+    //   stop execution and terminate the program
+    fn op_halt(&mut self, ptr: ByteAddr) -> Result<ByteAddr, Error> {
+        self.halted = true;
+        Ok(ptr)
+    }
+
+    //   1 a b
+    //   set register <a> to the value of <b>
+    fn op_set(&mut self, ptr: ByteAddr) -> Result<ByteAddr, Error> {
+        let register = self.read_register(ptr + 2)?;
+        let value = self.read_uint15(ptr + 4)?;
+        self.registers[register] = value;
+        Ok(ptr.past(Opcode::Set))
+    }
+
+    //   2 a
+    //   push <a> onto the stack
+    fn op_push(&mut self, ptr: ByteAddr) -> Result<ByteAddr, Error> {
+        let a = self.read_uint15(ptr + 2)?;
+        self.stack.push(a)?;
+        Ok(ptr.past(Opcode::Push))
+    }
+
+    //   3 a
+    //   remove the top element from the stack and write it into <a>; empty stack = error
+    fn op_pop(&mut self, ptr: ByteAddr) -> Result<ByteAddr, Error> {
+        let register = self.read_register(ptr + 2)?;
+
+        self.registers[register] = self.stack.pop()?;
+
+        Ok(ptr.past(Opcode::Pop))
+    }
+
+    //   4 a b c
+    //   set <a> to 1 if <b> is equal to <c>; set it to 0 otherwise
+    fn op_eq(&mut self, ptr: ByteAddr) -> Result<ByteAddr, Error> {
+        let register = self.read_register(ptr + 2)?;
+        let a = self.read_uint15(ptr + 4)?;
+        let b = self.read_uint15(ptr + 6)?;
+
+        self.registers[register] = (a == b) as u16;
+
+        Ok(ptr.past(Opcode::Eq))
+    }
+
+    //   5 a b c
+    //   set <a> to 1 if <b> is greater than <c>; set it to 0 otherwise
+    fn op_gt(&mut self, ptr: ByteAddr) -> Result<ByteAddr, Error> {
+        let register = self.read_register(ptr + 2)?;
+        let a = self.read_uint15(ptr + 4)?;
+        let b = self.read_uint15(ptr + 6)?;
+
+        self.registers[register] = (a > b) as u16;
+
+        Ok(ptr.past(Opcode::Gt))
+    }
+
+    //   6 a
+    //   jump to <a>
+    fn op_jmp(&mut self, ptr: ByteAddr) -> Result<ByteAddr, Error> {
+        self.read_uint15_address(ptr + 2)
+    }
+
+    //   7 a b
+    //   if <a> is nonzero, jump to <b>
+    fn op_jt(&mut self, ptr: ByteAddr) -> Result<ByteAddr, Error> {
+        let new_ptr = match self.read_uint15(ptr + 2)? {
+            1..=u16::MAX => self.read_uint15_address(ptr + 4)?,
+            0 => ptr.past(Opcode::Jt),
+        };
+
+        Ok(new_ptr)
+    }
+
+    //   8 a b
+    //   if <a> is zero, jump to <b>
+    fn op_jf(&mut self, ptr: ByteAddr) -> Result<ByteAddr, Error> {
+        let new_ptr = match self.read_uint15(ptr + 2)? {
+            0 => self.read_uint15_address(ptr + 4)?,
+            1..=u16::MAX => ptr.past(Opcode::Jf),
+        };
+
+        Ok(new_ptr)
+    }
+
+    operator_operation! {
+        op_add  with (a, b) is ((a + b) % Self::REGISTER_1)
+        op_mult with (a, b) is ((a as u32 * b as u32 % Self::REGISTER_1 as u32) as u16)
+        op_mod  with (a, b) is (a.checked_rem(b).ok_or(Error::DivideByZero)?)
+        op_and  with (a, b) is (a & b)
+        op_or   with (a, b) is (a | b)
+        op_not  with (a)    is (!a & Self::ADDRESS_SPACE)
+
+        op_add_checked  with (a, b) is (a.checked_add(b).ok_or(Error::ArithmeticOverflow)? % Self::REGISTER_1)
+        op_mult_checked with (a, b) is (a.checked_mul(b).ok_or(Error::ArithmeticOverflow)? % Self::REGISTER_1)
+    }
+
+    //   15 a b
+    //   read memory at address <b> and write it to <a>
+    fn op_rmem(&mut self, ptr: ByteAddr) -> Result<ByteAddr, Error> {
+        let register = self.read_register(ptr + 2)?;
+        let addr = self.read_uint15_address(ptr + 4)?;
+        let value = self.read_uint15(addr)?;
+
+        self.registers[register] = value;
+
+        Ok(ptr.past(Opcode::Rmem))
+    }
+
+    //   16 a b
+    //   write the value from <b> into memory at address <a>
+    fn op_wmem(&mut self, ptr: ByteAddr) -> Result<ByteAddr, Error> {
+        let addr = self.read_uint15_address(ptr + 2)?;
+        let value = self.read_uint15(ptr + 4)?;
+
+        // both bytes of the word have to be in RAM, not just the first
+        let start = addr.0 as usize;
+        let Some(word) = self.ram.get_mut(start..start + 2) else {
+            return Err(Error::InvalidAddress {
+                value: addr.to_word().0,
+                at: Some((ptr + 2).0),
+            });
+        };
+
+        let old = u16::from_le_bytes([word[0], word[1]]);
+        word.copy_from_slice(&value.to_le_bytes());
+        self.last_write = (addr.0, old, value);
+
+        Ok(ptr.past(Opcode::Wmem))
+    }
+
+    //   17 a
+    //   write the address of the next instruction to the stack and jump to <a>
+    fn op_call(&mut self, ptr: ByteAddr) -> Result<ByteAddr, Error> {
+        self.stack.push(ptr.past(Opcode::Call).to_word().0)?;
+
+        let addr = self.read_uint15_address(ptr + 2)?;
+        Ok(addr)
+    }
+
+    //   18
+    //   remove the top element from the stack and jump to it; empty stack = halt
+    fn op_ret(&mut self, ptr: ByteAddr) -> Result<ByteAddr, Error> {
+        let Ok(addr) = self.stack.pop() else {
+            self.halted = true;
+            return Ok(ptr);
+        };
+
+        Ok(WordAddr(addr).to_byte())
+    }
+
+    //   19 a
+    //   write the character represented by ascii code <a> to the terminal
+    fn op_out(&mut self, ptr: ByteAddr, io: &mut impl Io) -> Result<ByteAddr, Error> {
+        let char = self.read_uint15(ptr + 2)? as u8;
+        io.write_byte(char)?;
+        Ok(ptr.past(Opcode::Out))
+    }
+
+    //   20 a
+    //   read a character from the terminal and write its ascii code to <a>; it can be assumed that once input starts, it will continue until a newline is encountered; this means that you can safely read whole lines from the keyboard instead of having to figure out how to read individual characters
+    fn op_in(&mut self, ptr: ByteAddr, io: &mut impl Io) -> Result<ByteAddr, Error> {
+        let Some(byte) = io.read_byte()? else {
+            return Err(Error::InputClosed);
+        };
+
+        let register = self.read_register(ptr + 2)?;
+        // with small enough words not every byte is a value
+        let value = u16::from(byte);
+        if value > Self::ADDRESS_SPACE {
+            return Err(Error::InvalidUint15 { value, at: None });
+        }
+        self.registers[register] = value;
+
+        Ok(ptr.past(Opcode::In))
+    }
+
+    fn op_noop(&mut self, ptr: ByteAddr) -> Result<ByteAddr, Error> {
+        Ok(ptr.past(Opcode::Noop))
+    }
+
+    fn op_invalid_io(&mut self, _: ByteAddr) -> Result<ByteAddr, Error> {
+        unreachable!("I/O operations are not dispatched through the table")
+    }
+
+    fn read_word(&self, ptr: ByteAddr) -> Result<u16, Error> {
+        let start = ptr.0 as usize;
+        let Some(&[byte1, byte2]) = self.ram.get(start..start + 2) else {
+            return Err(Error::InvalidAddress {
+                value: ptr.to_word().0,
+                at: None,
+            });
+        };
+        Ok(u16::from_le_bytes([byte1, byte2]))
+    }
+
+    // the ranges depend on the architecture, so they can't be match patterns
+    fn value(word: u16) -> Value {
+        if word <= Self::ADDRESS_SPACE {
+            Value::Literal(word)
+        } else if word <= Self::REGISTER_SPACE {
+            Value::Register((word - Self::REGISTER_1) as usize)
+        } else {
+            Value::Invalid
+        }
+    }
+
+    fn read_uint15(&self, ptr: ByteAddr) -> Result<u16, Error> {
+        let uint15 = self.read_word(ptr)?;
+
+        match Self::value(uint15) {
+            Value::Literal(value) => Ok(value),
+            Value::Register(register) => Ok(self.registers[register]),
+            Value::Invalid => Err(Error::InvalidUint15 {
+                value: uint15,
+                at: Some(ptr.0),
+            }),
+        }
+    }
+
+    fn read_register(&self, ptr: ByteAddr) -> Result<usize, Error> {
+        let uint15 = self.read_word(ptr)?;
+
+        match Self::value(uint15) {
+            Value::Literal(_) => Err(Error::InvalidRegister {
+                value: uint15,
+                at: Some(ptr.0),
+            }),
+            Value::Register(register) => Ok(register),
+            Value::Invalid => Err(Error::InvalidUint15 {
+                value: uint15,
+                at: Some(ptr.0),
+            }),
+        }
+    }
+
+    // reads a word address operand and turns it into the byte offset it points at
+    fn read_uint15_address(&self, ptr: ByteAddr) -> Result<ByteAddr, Error> {
+        let uint15 = self.read_word(ptr)?;
+
+        let addr = match Self::value(uint15) {
+            Value::Literal(addr) => WordAddr(addr),
+            Value::Register(register) => WordAddr(self.registers[register]),
+            Value::Invalid => {
+                return Err(Error::InvalidAddress {
+                    value: uint15,
+                    at: Some(ptr.0),
+                });
+            }
+        };
+        Ok(addr.to_byte())
+    }
+}
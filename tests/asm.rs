@@ -0,0 +1,141 @@
+use vm_thingy::asm::{AssembleError, assemble};
+use vm_thingy::disasm::{disassemble, format_listing};
+
+#[test]
+fn assembles_registers_and_literals() {
+    let bin = assemble(
+        "
+        set r0 10   ; counter
+        out 0x41
+        halt
+        ",
+    )
+    .unwrap();
+
+    let words: Vec<u16> = bin
+        .chunks(2)
+        .map(|pair| u16::from_le_bytes([pair[0], pair[1]]))
+        .collect();
+
+    assert_eq!(words, vec![1, 32768, 10, 19, 65, 0]);
+}
+
+#[test]
+fn round_trips_through_the_disassembler() {
+    let source = "add r0 r1 0x0004\nout r0\njt r0 0x0141\nhalt\n";
+    let bin = assemble(source).unwrap();
+
+    let listing = disassemble(&bin, 0, u16::MAX);
+
+    let lines: Vec<&str> = listing.iter().map(|(_, line)| line.as_str()).collect();
+    assert_eq!(lines.join("\n") + "\n", source);
+    assert!(format_listing(&listing).starts_with("0x0000  add r0 r1 0x0004\n"));
+}
+
+#[test]
+fn reports_errors_with_line_numbers() {
+    assert_eq!(
+        assemble("noop\nfoo r0"),
+        Err(AssembleError::UnknownMnemonic {
+            line: 2,
+            mnemonic: "foo".to_string()
+        })
+    );
+    assert_eq!(
+        assemble("set r8 1"),
+        Err(AssembleError::InvalidOperand {
+            line: 1,
+            operand: "r8".to_string()
+        })
+    );
+    assert_eq!(
+        assemble("out 32768"),
+        Err(AssembleError::InvalidOperand {
+            line: 1,
+            operand: "32768".to_string()
+        })
+    );
+    assert_eq!(
+        assemble("set r0"),
+        Err(AssembleError::OperandCount {
+            line: 1,
+            expected: 2,
+            found: 1
+        })
+    );
+}
+
+#[test]
+fn emits_data_words_and_strings() {
+    let bin = assemble(
+        r#"
+        .word 1 0x8000 0xffff
+        dw r7
+        .string "a;\"\n"  ; one word per character
+        "#,
+    )
+    .unwrap();
+
+    let words: Vec<u16> = bin
+        .chunks(2)
+        .map(|pair| u16::from_le_bytes([pair[0], pair[1]]))
+        .collect();
+
+    assert_eq!(words, vec![1, 0x8000, 0xffff, 32775, 97, 59, 34, 10]);
+}
+
+#[test]
+fn prints_a_string_table_read_with_rmem() {
+    use std::io::Read;
+
+    let bin = assemble(
+        r#"
+        set r0 18       ; the string
+        rmem r1 r0
+        jf r1 17
+        out r1
+        add r0 r0 1
+        jmp 3
+        halt
+        .string "hi;\n"
+        .word 0
+        "#,
+    )
+    .unwrap();
+
+    let (mut state, (mut stdout, _stdin)) = vm_thingy::vm::State::init_with(&bin);
+    while !state.done() {
+        state.next().unwrap();
+    }
+    drop(state);
+
+    let mut output = String::new();
+    stdout.read_to_string(&mut output).unwrap();
+    assert_eq!(output, "hi;\n");
+}
+
+#[test]
+fn rejects_bad_data() {
+    assert_eq!(
+        assemble(".word 65536"),
+        Err(AssembleError::InvalidOperand {
+            line: 1,
+            operand: "65536".to_string()
+        })
+    );
+    assert_eq!(
+        assemble("dw"),
+        Err(AssembleError::OperandCount {
+            line: 1,
+            expected: 1,
+            found: 0
+        })
+    );
+    assert_eq!(
+        assemble(".string \"unterminated"),
+        Err(AssembleError::InvalidOperand {
+            line: 1,
+            operand: "\"unterminated".to_string()
+        })
+    );
+}
@@ -0,0 +1,403 @@
+use std::io::{self, Write};
+use std::sync::{Arc, Mutex};
+
+use vm_thingy::asm::assemble;
+use vm_thingy::vm::{DebugCmd, DebugResp, Error, State, WatchHit};
+
+#[test]
+fn stops_on_breakpoints_and_resumes() {
+    let bin = assemble(
+        "
+        set r0 3          ; 0x00
+        add r0 r0 32767   ; 0x06
+        jt r0 3           ; 0x0e
+        halt              ; 0x14
+        ",
+    )
+    .unwrap();
+    let (mut state, _io) = State::init_with(&bin);
+
+    state.add_breakpoint(0x0e);
+
+    assert_eq!(state.run_until_breakpoint().unwrap(), Some(0x0e));
+    assert_eq!(state.register(0), Some(2));
+    // running again stays put, resuming goes on to the next time around
+    assert_eq!(state.run_until_breakpoint().unwrap(), Some(0x0e));
+    assert_eq!(state.register(0), Some(2));
+    for expected in [1, 0] {
+        assert_eq!(state.resume_from_breakpoint().unwrap(), Some(0x0e));
+        assert_eq!(state.register(0), Some(expected));
+    }
+
+    assert_eq!(state.resume_from_breakpoint().unwrap(), None);
+    assert!(state.done());
+    assert_eq!(state.resume_from_breakpoint().unwrap(), None);
+}
+
+#[test]
+fn stops_on_a_breakpoint_before_the_first_instruction() {
+    let bin = assemble("out 65\nhalt").unwrap();
+    let (mut state, _io) = State::init_with(&bin);
+
+    state.add_breakpoint(0);
+
+    assert_eq!(state.run_until_breakpoint().unwrap(), Some(0));
+    assert_eq!(state.cycles(), 0);
+    assert_eq!(state.resume_from_breakpoint().unwrap(), None);
+    assert_eq!(state.cycles(), 2);
+}
+
+#[test]
+fn removed_breakpoints_are_skipped() {
+    let bin = assemble("noop\nnoop\nhalt").unwrap();
+    let (mut state, _io) = State::init_with(&bin);
+
+    state.add_breakpoint(2);
+    state.remove_breakpoint(2);
+
+    assert_eq!(state.run_until_breakpoint().unwrap(), None);
+    assert_eq!(state.cycles(), 3);
+}
+
+#[test]
+fn reports_writes_to_watched_words() {
+    let bin = assemble(
+        "
+        wmem 100 7    ; 0x00
+        wmem 101 8    ; 0x06
+        wmem 100 9    ; 0x0c
+        halt
+        ",
+    )
+    .unwrap();
+    let (mut state, _io) = State::init_with(&bin);
+
+    state.add_watchpoint(200);
+
+    let hit = state.run_until_watchpoint().unwrap().unwrap();
+    assert_eq!((hit.addr, hit.old, hit.new, hit.pc), (200, 0, 7, 0x00));
+
+    let hit = state.run_until_watchpoint().unwrap().unwrap();
+    assert_eq!((hit.addr, hit.old, hit.new, hit.pc), (200, 7, 9, 0x0c));
+
+    assert_eq!(state.run_until_watchpoint().unwrap(), None);
+}
+
+#[derive(Clone, Default)]
+struct SharedBuffer(Arc<Mutex<Vec<u8>>>);
+
+impl Write for SharedBuffer {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.lock().unwrap().write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl SharedBuffer {
+    fn contents(&self) -> String {
+        String::from_utf8(self.0.lock().unwrap().clone()).unwrap()
+    }
+}
+
+#[test]
+fn traces_executed_instructions() {
+    let bin = assemble("set r1 4\nadd r0 r1 1\nhalt").unwrap();
+    let (mut state, _io) = State::init_with(&bin);
+    let trace = SharedBuffer::default();
+
+    state.enable_trace(Box::new(trace.clone()));
+    while !state.done() {
+        state.next().unwrap();
+    }
+
+    assert_eq!(
+        trace.contents(),
+        "0x0000  set r1 0x0004  [r1=0x0000]\n\
+         0x0006  add r0 r1 0x0001  [r0=0x0000 r1=0x0004]\n\
+         0x000e  halt\n"
+    );
+}
+
+#[test]
+fn traces_only_the_given_range() {
+    let bin = assemble("set r1 4\nadd r0 r1 1\nnoop\nhalt").unwrap();
+    let (mut state, _io) = State::init_with(&bin);
+    let trace = SharedBuffer::default();
+
+    state.enable_trace(Box::new(trace.clone()));
+    state.set_trace_range(6, 16);
+    while !state.done() {
+        state.next().unwrap();
+    }
+
+    assert_eq!(
+        trace.contents(),
+        "0x0006  add r0 r1 0x0001  [r0=0x0000 r1=0x0004]\n\
+         0x000e  noop\n"
+    );
+}
+
+#[test]
+fn step_back_undoes_instructions() {
+    let bin = assemble("set r0 1\npush 5\nwmem 100 7\nadd r0 r0 1\nhalt").unwrap();
+    let (mut state, _) = State::init_with(&bin);
+    state.enable_history(2);
+
+    assert!(matches!(state.step_back(), Err(Error::HistoryEmpty)));
+
+    state.next().unwrap();
+    state.next().unwrap();
+    state.next().unwrap();
+    let after_wmem = state.snapshot();
+    state.next().unwrap();
+    assert_eq!(state.register(0), Some(2));
+
+    state.step_back().unwrap();
+    assert_eq!(state.snapshot(), after_wmem);
+
+    state.step_back().unwrap();
+    assert_eq!(state.read_word(100).unwrap(), 0);
+    assert_eq!(state.program_counter(), 10);
+
+    // only two instructions were kept
+    assert!(matches!(state.step_back(), Err(Error::HistoryEmpty)));
+}
+
+#[test]
+fn history_is_off_by_default() {
+    let (mut state, _) = State::init_with(&assemble("noop\nhalt").unwrap());
+
+    state.next().unwrap();
+    assert!(matches!(state.step_back(), Err(Error::HistoryEmpty)));
+
+    state.enable_history(4);
+    state.next().unwrap();
+    state.disable_history();
+    assert!(matches!(state.step_back(), Err(Error::HistoryEmpty)));
+}
+
+#[test]
+fn reports_writes_over_executed_code() {
+    // overwrites the out's operand after running it, then data nobody ran
+    let bin = assemble("out 65\nwmem 1 66\nwmem 100 1\nwmem 0 19\nhalt").unwrap();
+    let output = SharedBuffer::default();
+    let mut state = State::with_io(&bin, io::empty(), output.clone());
+    state.detect_code_writes(true);
+
+    while !state.done() {
+        state.next().unwrap();
+    }
+
+    let writes = state.take_code_writes();
+    assert_eq!(
+        writes,
+        [
+            WatchHit {
+                addr: 2,
+                old: 65,
+                new: 66,
+                pc: 4
+            },
+            WatchHit {
+                addr: 0,
+                old: 19,
+                new: 19,
+                pc: 16
+            },
+        ]
+    );
+    assert!(state.take_code_writes().is_empty());
+}
+
+#[test]
+fn stops_before_executing_data() {
+    // jumps over the halt into the text, 'h' isn't an opcode
+    let bin = assemble("jmp 3\nhalt\nout 104").unwrap();
+    let (mut state, _) = State::init_with(&bin);
+    state.write_word(3, 104).unwrap();
+    state.detect_data_execution(true);
+
+    state.next().unwrap();
+    assert!(matches!(state.next(), Err(Error::ExecutingData(6))));
+    assert_eq!(state.program_counter(), 6);
+    assert!(!state.coverage()[6]);
+}
+
+#[test]
+fn code_writes_are_not_recorded_by_default() {
+    let (mut state, _) = State::init_with(&assemble("wmem 0 21\nhalt").unwrap());
+
+    state.next().unwrap();
+    assert!(state.take_code_writes().is_empty());
+}
+
+#[test]
+fn dumps_memory_as_hex_and_ascii() {
+    let mut bin = b"Hello, world!\n".to_vec();
+    bin.extend([0, 1, 2, 3, 0x41]);
+    let (state, _) = State::init_with(&bin);
+
+    let mut dump = vec![];
+    state.dump_memory(2, 21, &mut dump).unwrap();
+
+    assert_eq!(
+        String::from_utf8(dump).unwrap(),
+        "0x0002  6c 6c 6f 2c 20 77 6f 72 6c 64 21 0a 00 01 02 03 |llo, world!.....|\n\
+         0x0012  41 00 00                                        |A..|\n"
+    );
+}
+
+#[test]
+fn dump_clamps_to_ram() {
+    let (state, _) = State::init_with(&[]);
+
+    let mut dump = vec![];
+    state.dump_memory(65530, u16::MAX, &mut dump).unwrap();
+    assert_eq!(
+        String::from_utf8(dump).unwrap(),
+        "0xfffa  00 00 00 00 00                                  |.....|\n"
+    );
+
+    let mut dump = vec![];
+    state.dump_memory(10, 4, &mut dump).unwrap();
+    assert!(dump.is_empty());
+}
+
+#[test]
+fn debugger_controls_run_for_from_another_thread() {
+    // counts r0 up forever
+    let bin = assemble("add r0 r0 1\njmp 0").unwrap();
+    let (mut state, _) = State::init_with(&bin);
+    let (commands, responses) = state.attach_debugger();
+    state.set_instruction_budget(2_000_000);
+
+    commands.send(DebugCmd::SetBreakpoint(8)).unwrap();
+    let vm = std::thread::spawn(move || state.run_for(u64::MAX));
+
+    assert_eq!(responses.recv().unwrap(), DebugResp::Done);
+    assert_eq!(responses.recv().unwrap(), DebugResp::Paused(8));
+    commands.send(DebugCmd::ReadReg(0)).unwrap();
+    assert_eq!(responses.recv().unwrap(), DebugResp::Register(Some(1)));
+
+    commands.send(DebugCmd::RemoveBreakpoint(8)).unwrap();
+    commands.send(DebugCmd::Resume).unwrap();
+    assert_eq!(responses.recv().unwrap(), DebugResp::Done);
+    assert_eq!(responses.recv().unwrap(), DebugResp::Resumed);
+
+    commands.send(DebugCmd::Pause).unwrap();
+    let DebugResp::Paused(_) = responses.recv().unwrap() else {
+        panic!("expected to pause");
+    };
+    commands.send(DebugCmd::ReadReg(0)).unwrap();
+    let DebugResp::Register(Some(count)) = responses.recv().unwrap() else {
+        panic!("expected a register");
+    };
+    // the pause may have been read together with the resume
+    assert!(count >= 1);
+
+    // detaches, so the loop runs on until it's out of budget
+    drop(commands);
+    assert!(matches!(vm.join().unwrap(), Err(Error::BudgetExceeded)));
+}
+
+#[test]
+fn finds_every_occurrence_of_bytes() {
+    let (state, _) = State::init_with(b"abcabcaab");
+
+    assert_eq!(state.find_bytes(b"abc"), [0, 3]);
+    assert_eq!(state.find_bytes(b"a"), [0, 3, 6, 7]);
+    assert_eq!(state.find_bytes(b"cab"), [2]);
+    assert!(state.find_bytes(b"abd").is_empty());
+    assert!(state.find_bytes(b"").is_empty());
+}
+
+#[test]
+fn counts_writes_per_word_while_profiling() {
+    // the first write to word 100 happens before profiling starts
+    let bin = assemble("wmem 100 1\nwmem 101 2\nwmem 100 3\nwmem 100 4\nhalt").unwrap();
+    let (mut state, _) = State::init_with(&bin);
+    state.next().unwrap();
+    state.enable_write_profiling(true);
+    while !state.done() {
+        state.next().unwrap();
+    }
+
+    assert_eq!(state.write_count(200), 2);
+    assert_eq!(state.write_count(201), 2);
+    assert_eq!(state.write_count(202), 1);
+    assert_eq!(state.write_count(0), 0);
+
+    state.reset();
+    assert_eq!(state.write_count(200), 0);
+    state.next().unwrap();
+    assert_eq!(state.write_count(200), 1);
+    state.enable_write_profiling(false);
+    assert_eq!(state.write_count(200), 0);
+}
+
+#[test]
+fn compares_states_in_lockstep() {
+    let bin = assemble("set r0 5\npush r0\nwmem 100 7\nhalt").unwrap();
+    let (mut a, _) = State::init_with(&bin);
+    let (mut b, _) = State::init_with(&bin);
+
+    while !a.done() {
+        a.next().unwrap();
+        b.next().unwrap();
+        assert!(a.state_eq(&b), "{:?}", a.state_diff(&b));
+    }
+
+    b.set_register(3, 9).unwrap();
+    b.write_word(100, 8).unwrap();
+    b.reset_registers();
+    assert!(!a.state_eq(&b));
+    assert_eq!(
+        a.state_diff(&b),
+        [
+            "pc: 0x0010 != 0x0000",
+            "done: true != false",
+            "r0: 0x0005 != 0x0000",
+            "stack: [5] != []",
+            "ram 0x00c8: 0x0007 != 0x0008",
+        ]
+    );
+}
+
+#[test]
+fn steps_over_calls() {
+    let bin = assemble(
+        "
+        set r0 3          ; 0x00
+        call 6            ; 0x06
+        halt              ; 0x0a
+        jf r0 15          ; 0x0c
+        add r0 r0 32767   ; 0x12
+        call 6            ; 0x1a
+        ret               ; 0x1e
+        ",
+    )
+    .unwrap();
+    let (mut state, _io) = State::init_with(&bin);
+
+    state.step_over().unwrap();
+    assert_eq!(state.program_counter(), 0x06);
+    state.step_over().unwrap();
+    assert_eq!(state.program_counter(), 0x0a);
+    assert_eq!(state.register(0), Some(0));
+    assert!(state.stack().is_empty());
+
+    // the recursive calls come back through 0x1e, only the outermost one stops
+    state.reset();
+    state.add_breakpoint(0x1a);
+    state.run_until_breakpoint().unwrap();
+    state.remove_breakpoint(0x1a);
+    state.step_over().unwrap();
+    assert_eq!(state.program_counter(), 0x1e);
+    assert_eq!(state.stack(), [0x0a / 2]);
+
+    // and the temporary breakpoint is gone again
+    assert_eq!(state.run_until_breakpoint().unwrap(), None);
+}
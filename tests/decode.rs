@@ -0,0 +1,105 @@
+use proptest::prelude::*;
+use vm_thingy::asm::assemble;
+use vm_thingy::decode::{Instruction, Operand, decode};
+use vm_thingy::vm::{Error, Opcode, instruction_length};
+
+fn assemble_words(words: &[u16]) -> Vec<u8> {
+    words.iter().flat_map(|word| word.to_le_bytes()).collect()
+}
+
+#[test]
+fn decodes_operands_and_next_address() {
+    let ram = assemble_words(&[21, 9, 32768, 32769, 4]);
+
+    assert_eq!(decode(&ram, 0).unwrap(), (Instruction::Noop, 1));
+    assert_eq!(
+        decode(&ram, 1).unwrap(),
+        (
+            Instruction::Add {
+                dst: Operand::Register(0),
+                a: Operand::Register(1),
+                b: Operand::Literal(4),
+            },
+            5
+        )
+    );
+}
+
+#[test]
+fn rejects_invalid_words() {
+    let ram = assemble_words(&[22, 2, 32776, 2]);
+
+    assert!(matches!(
+        decode(&ram, 0),
+        Err(Error::InvalidInstruction(22))
+    ));
+    assert!(matches!(
+        decode(&ram, 1),
+        Err(Error::InvalidUint15 {
+            value: 32776,
+            at: Some(4)
+        })
+    ));
+    assert!(matches!(
+        decode(&ram, 3),
+        Err(Error::InvalidAddress { value: 4, at: None })
+    ));
+}
+
+fn operand() -> impl Strategy<Value = Operand> {
+    prop_oneof![
+        (0..32768u16).prop_map(Operand::Literal),
+        (0..8u8).prop_map(Operand::Register),
+    ]
+}
+
+// an opcode with as many operands as it takes
+fn instruction() -> impl Strategy<Value = (Opcode, Vec<Operand>)> {
+    (0..22u16, prop::collection::vec(operand(), 3)).prop_map(|(value, mut operands)| {
+        let opcode = Opcode::try_from(value).unwrap();
+        operands.truncate(opcode.operand_count() as usize);
+        (opcode, operands)
+    })
+}
+
+proptest! {
+    #[test]
+    fn assembled_instructions_decode_back(program in prop::collection::vec(instruction(), 1..32)) {
+        let source: String = program
+            .iter()
+            .map(|(opcode, operands)| {
+                let operands: String = operands.iter().map(|operand| format!(" {operand}")).collect();
+                format!("{}{operands}\n", opcode.mnemonic())
+            })
+            .collect();
+        let ram = assemble(&source).unwrap();
+
+        let mut addr = 0;
+        for (opcode, operands) in &program {
+            let (instruction, next) = decode(&ram, addr).unwrap();
+            prop_assert_eq!(instruction.opcode(), *opcode);
+            prop_assert_eq!(&instruction.operands(), operands);
+            prop_assert_eq!(next, addr + 1 + opcode.operand_count());
+            addr = next;
+        }
+        prop_assert_eq!(addr as usize * 2, ram.len());
+    }
+}
+
+#[test]
+fn instruction_lengths_match_the_decoder() {
+    assert_eq!(instruction_length(0), Some(1));
+    assert_eq!(instruction_length(2), Some(2));
+    assert_eq!(instruction_length(1), Some(3));
+    assert_eq!(instruction_length(9), Some(4));
+
+    for opcode in 0..=u16::MAX {
+        // every operand is r0, which is valid anywhere
+        let ram = assemble_words(&[opcode, 32768, 32768, 32768]);
+        match (instruction_length(opcode), decode(&ram, 0)) {
+            (Some(length), Ok((_, next))) => assert_eq!(next, length),
+            (None, Err(Error::InvalidInstruction(word))) => assert_eq!(word, opcode),
+            (length, decoded) => panic!("{opcode}: {length:?} but decoded {decoded:?}"),
+        }
+    }
+}
@@ -0,0 +1,80 @@
+use vm_thingy::disasm::{disassemble, format_listing};
+
+fn assemble_words(words: &[u16]) -> Vec<u8> {
+    words.iter().flat_map(|word| word.to_le_bytes()).collect()
+}
+
+#[test]
+fn disassembles_variable_length_instructions() {
+    let ram = assemble_words(&[
+        9, 32768, 32769, 4, // add r0 r1 4
+        19, 32768, // out r0
+        7, 32768, 0x0141, // jt r0 0x0141
+        0,      // halt
+    ]);
+
+    let lines = disassemble(&ram, 0, 10);
+
+    assert_eq!(
+        lines,
+        vec![
+            (0, "add r0 r1 0x0004".to_string()),
+            (4, "out r0".to_string()),
+            (6, "jt r0 0x0141".to_string()),
+            (9, "halt".to_string()),
+        ]
+    );
+}
+
+#[test]
+fn marks_undecodable_words_as_data() {
+    let ram = assemble_words(&[22, 1, 40000, 21]);
+
+    let lines = disassemble(&ram, 0, 4);
+
+    assert_eq!(
+        lines,
+        vec![
+            (0, ".word 0x0016".to_string()),
+            (1, ".word 0x0001".to_string()),
+            (2, ".word 0x9c40".to_string()),
+            (3, "noop".to_string()),
+        ]
+    );
+}
+
+#[test]
+fn stops_at_the_end_of_ram() {
+    let ram = assemble_words(&[21, 6]);
+
+    let lines = disassemble(&ram, 0, u16::MAX);
+
+    assert_eq!(
+        format_listing(&lines),
+        "0x0000  noop\n0x0001  .word 0x0006\n"
+    );
+}
+
+#[test]
+fn comments_characters_going_in_and_out() {
+    let ram = assemble_words(&[
+        19, 0x41, // out 'A'
+        19, 10, // out '\n'
+        19, 32768, // out r0
+        20, 32769, // in r1
+        19, 200, // out beyond ascii
+    ]);
+
+    let lines = disassemble(&ram, 0, 10);
+
+    assert_eq!(
+        lines,
+        vec![
+            (0, "out 0x0041  ; 'A'".to_string()),
+            (2, "out 0x000a  ; '\\n'".to_string()),
+            (4, "out r0".to_string()),
+            (6, "in r1  ; read a character".to_string()),
+            (8, "out 0x00c8".to_string()),
+        ]
+    );
+}
@@ -0,0 +1,290 @@
+use vm_thingy::asm::assemble;
+use vm_thingy::vm::{Error, Opcode, State, instruction_length};
+
+// runs the assembled program to halt, returning the state and everything it printed
+fn run_with_input(source: &str, input: &[u8]) -> (State, Vec<u8>) {
+    let bin = assemble(source).unwrap();
+    let (mut state, _) = State::init_with(&bin);
+    let output = state.run_with_input(input).unwrap();
+    (state, output)
+}
+
+fn run(source: &str) -> State {
+    run_with_input(source, b"").0
+}
+
+fn registers(state: &State) -> [u16; 8] {
+    std::array::from_fn(|idx| state.register(idx as u8).unwrap())
+}
+
+// runs until the first error
+fn run_to_error(source: &str) -> Error {
+    let bin = assemble(source).unwrap();
+    let (mut state, _) = State::init_with(&bin);
+    loop {
+        if let Err(err) = state.next() {
+            return err;
+        }
+    }
+}
+
+#[test]
+fn halt_stops_execution() {
+    let (state, output) = run_with_input("halt\nout 65", b"");
+
+    assert!(state.done());
+    assert_eq!(state.cycles(), 1);
+    assert!(output.is_empty());
+}
+
+#[test]
+fn set_copies_literals_and_registers() {
+    let state = run("set r3 1234\nset r4 r3\nhalt");
+
+    assert_eq!(registers(&state), [0, 0, 0, 1234, 1234, 0, 0, 0]);
+}
+
+#[test]
+fn push_and_pop_are_last_in_first_out() {
+    let state = run("set r1 9\npush 7\npush r1\npop r2\npop r3\nhalt");
+
+    assert_eq!(state.register(2), Some(9));
+    assert_eq!(state.register(3), Some(7));
+}
+
+#[test]
+fn pop_on_empty_stack_is_an_error() {
+    assert!(matches!(run_to_error("pop r0"), Error::EmptyStack));
+}
+
+#[test]
+fn eq_and_gt_compare() {
+    let state = run("eq r0 5 5\neq r1 5 6\ngt r2 6 5\ngt r3 5 5\ngt r4 5 6\nhalt");
+
+    assert_eq!(registers(&state), [1, 0, 1, 0, 0, 0, 0, 0]);
+}
+
+#[test]
+fn jmp_to_literal_and_register() {
+    // jmp 4 skips the first out, jmp r0 the second
+    let (_, output) = run_with_input(
+        "jmp 4\nout 65\nset r0 11\njmp r0\nout 66\nout 67\nhalt",
+        b"",
+    );
+
+    assert_eq!(output, b"C");
+}
+
+#[test]
+fn jt_jumps_on_nonzero() {
+    // the untaken jump would land on the halt at 13
+    let (_, output) = run_with_input("set r1 11\njt 0 13\njt r1 r1\nout 65\nout 66\nhalt", b"");
+
+    assert_eq!(output, b"B");
+}
+
+#[test]
+fn jf_jumps_on_zero() {
+    // the untaken jump would land on the halt at 13
+    let (_, output) = run_with_input("set r1 11\njf 1 13\njf r0 r1\nout 65\nout 66\nhalt", b"");
+
+    assert_eq!(output, b"B");
+}
+
+#[test]
+fn add_wraps_at_32768() {
+    let state = run("add r0 32767 2\nadd r1 32767 1\nadd r2 r0 r0\nhalt");
+
+    assert_eq!(registers(&state), [1, 0, 2, 0, 0, 0, 0, 0]);
+}
+
+#[test]
+fn mult_does_not_overflow_on_large_operands() {
+    let state = run("mult r0 32767 32767\nmult r1 16384 2\nmult r2 300 100\nhalt");
+
+    assert_eq!(registers(&state), [1, 0, 30000, 0, 0, 0, 0, 0]);
+}
+
+#[test]
+fn checked_arithmetic_agrees_with_the_masked_math() {
+    let source = "add r0 32767 32767\nadd r1 32767 2\nmult r2 181 181\nmult r3 300 100\nhalt";
+    let (mut state, _) = State::init_with(&assemble(source).unwrap());
+    state.check_arithmetic(true);
+    state.run_with_input(b"").unwrap();
+
+    assert_eq!(registers(&state), registers(&run(source)));
+    assert_eq!(registers(&state), [32766, 1, 32761, 30000, 0, 0, 0, 0]);
+}
+
+#[test]
+fn checked_arithmetic_traps_products_that_wrap_a_u16() {
+    let (mut state, _) = State::init_with(&assemble("mult r0 300 300\nhalt").unwrap());
+    state.check_arithmetic(true);
+
+    assert!(matches!(
+        state.run_with_input(b""),
+        Err(Error::ArithmeticOverflow)
+    ));
+    assert_eq!(state.register(0), Some(0));
+    // 90000 % 32768, the masked math works on the full product
+    assert_eq!(run("mult r0 300 300\nhalt").register(0), Some(24464));
+}
+
+#[test]
+fn mod_takes_the_remainder() {
+    let state = run("mod r0 17 5\nmod r1 4 32767\nhalt");
+
+    assert_eq!(state.register(0), Some(2));
+    assert_eq!(state.register(1), Some(4));
+    assert!(matches!(run_to_error("mod r0 1 0"), Error::DivideByZero));
+}
+
+#[test]
+fn bad_operands_say_where_they_were_read() {
+    // the destination of the second instruction is a literal
+    let err = run_to_error("noop\nset 5 1");
+    assert!(matches!(
+        err,
+        Error::InvalidRegister {
+            value: 5,
+            at: Some(4)
+        }
+    ));
+    assert_eq!(err.to_string(), "Invalid Register: 5 at 0x0004");
+}
+
+#[test]
+fn bitwise_ops_stay_within_15_bits() {
+    let state = run(
+        "and r0 0x7f0f 0x00ff\nor r1 0x7000 0x000f\nnot r2 0\nnot r3 0x7fff\nnot r4 0x5555\nhalt",
+    );
+
+    assert_eq!(
+        registers(&state),
+        [0x000f, 0x700f, 0x7fff, 0, 0x2aaa, 0, 0, 0]
+    );
+}
+
+#[test]
+fn wmem_and_rmem_round_trip() {
+    let state = run("wmem 100 1234\nrmem r0 100\nset r1 101\nwmem r1 4321\nrmem r2 r1\nhalt");
+
+    assert_eq!(state.register(0), Some(1234));
+    assert_eq!(state.register(2), Some(4321));
+    assert_eq!(state.read_word(100).unwrap(), 1234);
+    assert_eq!(state.read_word(101).unwrap(), 4321);
+}
+
+#[test]
+fn wmem_reaches_the_highest_word() {
+    let state = run("wmem 32767 1234\nrmem r0 32767\nhalt");
+
+    assert_eq!(state.register(0), Some(1234));
+    assert_eq!(state.read_word(32767).unwrap(), 1234);
+    assert_eq!(state.ram()[0xfffe..], [0xd2, 0x04]);
+}
+
+#[test]
+fn call_returns_after_itself() {
+    // call 4 runs the subroutine after the padding noop, ret comes back to the halt
+    let state = run("call 4\nhalt\nnoop\nset r0 7\nret");
+
+    assert_eq!(state.register(0), Some(7));
+    assert_eq!(state.cycles(), 4);
+}
+
+#[test]
+fn ret_resumes_right_after_the_call() {
+    let (mut state, _) = State::init_with(&assemble("call 3\nhalt\nret").unwrap());
+
+    state.next().unwrap();
+    assert_eq!(state.program_counter(), 6);
+    state.next().unwrap();
+    assert_eq!(state.program_counter(), 4);
+    assert!(state.stack().is_empty());
+}
+
+#[test]
+fn nested_calls_return_right_after_each_call() {
+    // word 0: call 6, word 2: set r0 1, word 5: halt, word 6: call 10,
+    // word 8: ret, word 9: padding, word 10: set r1 2, word 13: ret
+    let source = "call 6\nset r0 1\nhalt\ncall 10\nret\nnoop\nset r1 2\nret";
+    let (mut state, _) = State::init_with(&assemble(source).unwrap());
+
+    // the pc is a byte offset, return addresses on the stack are words
+    state.next().unwrap();
+    assert_eq!((state.program_counter(), state.stack()), (12, &[2][..]));
+    state.next().unwrap();
+    assert_eq!((state.program_counter(), state.stack()), (20, &[2, 8][..]));
+    state.next().unwrap();
+    state.next().unwrap();
+    assert_eq!((state.program_counter(), state.stack()), (16, &[2][..]));
+    state.next().unwrap();
+    assert_eq!((state.program_counter(), state.stack()), (4, &[][..]));
+
+    state.run_with_input(b"").unwrap();
+    assert_eq!(registers(&state), [1, 2, 0, 0, 0, 0, 0, 0]);
+    assert_eq!(state.cycles(), 7);
+}
+
+#[test]
+fn call_through_a_register() {
+    let state = run("set r1 6\ncall r1\nhalt\nset r0 7\nret");
+
+    assert_eq!(state.register(0), Some(7));
+}
+
+#[test]
+fn ret_on_empty_stack_halts() {
+    let (state, output) = run_with_input("ret\nout 65", b"");
+
+    assert!(state.done());
+    assert!(output.is_empty());
+}
+
+#[test]
+fn in_and_out_echo_bytes() {
+    let (state, output) = run_with_input("in r0\nout r0\nin r1\nout r1\nout 10\nhalt", b"hi");
+
+    assert_eq!(output, b"hi\n");
+    assert_eq!(state.register(0), Some(b'h' as u16));
+    assert_eq!(state.register(1), Some(b'i' as u16));
+}
+
+#[test]
+fn noop_only_moves_on() {
+    let state = run("noop\nnoop\nhalt");
+
+    assert_eq!(registers(&state), [0; 8]);
+    assert_eq!(state.cycles(), 3);
+}
+
+#[test]
+fn instructions_that_fall_through_skip_their_length() {
+    let jumps = [
+        Opcode::Halt,
+        Opcode::Jmp,
+        Opcode::Jt,
+        Opcode::Jf,
+        Opcode::Call,
+        Opcode::Ret,
+    ];
+    for opcode in (0..).map_while(|word| Opcode::try_from(word).ok()) {
+        if jumps.contains(&opcode) {
+            continue;
+        }
+
+        // push 1 so pop has something, then the instruction with r0 = 1 for every operand
+        let words = [2, 1, opcode as u16, 32768, 32768, 32768];
+        let bin: Vec<u8> = words.iter().flat_map(|word| word.to_le_bytes()).collect();
+        let mut state = State::builder(&bin)
+            .register(0, 1)
+            .build_with_io(std::io::Cursor::new(b"x"), std::io::sink())
+            .unwrap();
+        state.next().unwrap();
+        state.next().unwrap();
+
+        let length = instruction_length(opcode as u16).unwrap();
+        assert_eq!(state.program_counter(), 4 + length * 2, "{opcode:?}");
+    }
+}
@@ -0,0 +1,1141 @@
+use std::collections::HashSet;
+use std::io::{Read, Write};
+
+use vm_thingy::solver::{
+    Controller, Inventory, Map, Observer, Transcript, VAULT_GRID, VaultRoom, coin_value, explore,
+    find_codes, fix_teleporter, interactive, is_darkness, place_coins, replay, send_command, solve,
+    solve_coins, solve_vault, teleporter_check,
+};
+use vm_thingy::vm::{Error, State};
+
+const COINS: [(&str, u16); 5] = [
+    ("red coin", 2),
+    ("corroded coin", 3),
+    ("shiny coin", 5),
+    ("concave coin", 7),
+    ("blue coin", 9),
+];
+
+#[test]
+fn coin_values_follow_their_faces() {
+    let descriptions = [
+        "This coin is made of a red metal.  It has two dots on one side.",
+        "This coin is somewhat corroded.  It has a triangle on one side.",
+        "This coin is somehow still quite shiny.  It has a pentagon on one side.",
+        "This coin is slightly rounded, almost like a tiny bowl.  It has seven dots on one side.",
+        "This coin is made of a blue metal.  It has nine dots on one side.",
+    ];
+    let values = descriptions.map(coin_value);
+    assert_eq!(values, [Some(2), Some(3), Some(5), Some(7), Some(9)]);
+
+    assert_eq!(coin_value("It has One dot and a Square."), Some(1));
+    assert_eq!(coin_value("It has 4 dots."), Some(4));
+    assert_eq!(coin_value("A lantern."), None);
+    assert_eq!(coin_value("dots all over"), None);
+}
+
+#[test]
+fn solves_the_monument_equation() {
+    let order = solve_coins(&COINS).unwrap();
+    assert_eq!(
+        order,
+        [
+            "blue coin",
+            "red coin",
+            "shiny coin",
+            "concave coin",
+            "corroded coin"
+        ]
+    );
+}
+
+#[test]
+fn solves_for_whatever_values_the_coins_have() {
+    // 10 + 2 * 5^2 + 7^3 - 4
+    let coins = [("a", 4), ("b", 7), ("c", 10), ("d", 5), ("e", 2)];
+    let order = solve_coins(&coins).unwrap();
+
+    let value = |name| coins.iter().find(|(coin, _)| *coin == name).unwrap().1 as i32;
+    let [a, b, c, d, e] = [0, 1, 2, 3, 4].map(|i| value(order[i]));
+    assert_eq!(a + b * c.pow(2) + d.pow(3) - e, 399);
+}
+
+#[test]
+fn rejects_missing_or_unsolvable_coins() {
+    assert_eq!(solve_coins(&COINS[..4]), None);
+    assert_eq!(solve_coins(&[("a", 1); 5]), None);
+}
+
+#[test]
+fn places_coins_through_the_controller() {
+    let mut commands: Vec<u8> = vec![];
+    assert!(place_coins(&mut commands, &COINS));
+    assert_eq!(
+        String::from_utf8(commands).unwrap(),
+        "use blue coin\nuse red coin\nuse shiny coin\nuse concave coin\nuse corroded coin\n"
+    );
+}
+
+// remembers how much had been written at each flush
+#[derive(Default)]
+struct FlushLog {
+    written: Vec<u8>,
+    flushed_at: Vec<usize>,
+}
+
+impl Write for FlushLog {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.written.write(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.flushed_at.push(self.written.len());
+        Ok(())
+    }
+}
+
+#[test]
+fn use_sequence_flushes_each_use() {
+    let things = ["red coin".to_string(), "blue coin".to_string()];
+    let mut log: Vec<u8> = vec![];
+    let mut transcript = Transcript::new(FlushLog::default(), &mut log);
+    transcript.use_sequence(&things);
+
+    let (commands, _) = transcript.into_inner();
+    assert_eq!(commands.written, b"use red coin\nuse blue coin\n");
+    assert_eq!(commands.flushed_at, [13, 27]);
+    assert_eq!(
+        String::from_utf8(log).unwrap(),
+        "[turn 1] > use red coin\n[turn 2] > use blue coin\n"
+    );
+}
+
+// takes at most one byte per write
+struct Trickle(Vec<u8>);
+
+impl Write for Trickle {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.write(&buf[..buf.len().min(1)])
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+#[test]
+fn write_controllers_send_whole_commands_right_away() {
+    let mut buffered = std::io::BufWriter::new(vec![]);
+    buffered.go_place("north");
+    assert_eq!(buffered.get_ref(), b"go north\n");
+
+    let mut trickle = Trickle(vec![]);
+    trickle.take_thing("tablet");
+    assert_eq!(trickle.0, b"take tablet\n");
+}
+
+#[test]
+fn teleporter_check_passes_for_the_known_energy() {
+    assert_eq!(teleporter_check(25734), 6);
+    assert_ne!(teleporter_check(1), 6);
+    assert_ne!(teleporter_check(25733), 6);
+}
+
+#[test]
+fn fix_teleporter_sets_energy_and_patches_verification() {
+    let mut bin = vec![0u8; 0x1790 * 2];
+    bin[0x1571 * 2..0x1573 * 2].copy_from_slice(&[17, 0, 0x8b, 0x17]);
+    let (mut state, _) = State::init_with(&bin);
+
+    fix_teleporter(&mut state, 25734).unwrap();
+
+    assert_eq!(state.register(7), Some(25734));
+    let patch: Vec<u16> = (0x178b..0x178f)
+        .map(|addr| state.read_word(addr).unwrap())
+        .collect();
+    assert_eq!(patch, [1, 0x8000, 6, 18]);
+}
+
+#[test]
+fn fix_teleporter_refuses_unknown_binaries() {
+    let (mut state, _) = State::init_with(&[0; 16]);
+
+    assert!(matches!(
+        fix_teleporter(&mut state, 25734),
+        Err(Error::InvalidInstruction(0))
+    ));
+    assert_eq!(state.register(7), Some(0));
+}
+
+const FOOTHILLS: &str = "\
+== Foothills ==
+You find yourself standing at the base of an enormous mountain.
+
+Things of interest here:
+- tablet
+
+There are 2 exits:
+- doorway
+- south
+
+What do you do?
+";
+
+const DARK_CAVE: &str = "\
+== Dark cave ==
+This seems to be the mouth of a deep cave.
+
+There is 1 exit:
+- south
+
+What do you do?
+";
+
+fn feed(map: &mut Map, output: &str) {
+    output.lines().for_each(|line| map.read_line(line));
+}
+
+#[test]
+fn map_parses_room_blocks() {
+    let mut map = Map::new();
+    feed(&mut map, FOOTHILLS);
+
+    let room = map.current().unwrap();
+    assert_eq!(room.name, "Foothills");
+    assert_eq!(
+        room.description,
+        "You find yourself standing at the base of an enormous mountain."
+    );
+    assert_eq!(room.things, ["tablet"]);
+    assert_eq!(room.exits, ["doorway", "south"]);
+    assert!(room.traversed.is_empty());
+}
+
+#[test]
+fn map_normalizes_thing_names() {
+    let mut map = Map::new();
+    feed(
+        &mut map,
+        "\
+== Ruins ==
+You stand in the massive central hall of these ruins.
+
+Things of interest here:
+- red coin.
+-  empty   lantern  
+- 
+
+There is 1 exit:
+- north
+
+What do you do?
+",
+    );
+
+    let room = map.current().unwrap();
+    assert_eq!(room.things, ["red coin", "empty lantern"]);
+    assert_eq!(room.exits, ["north"]);
+}
+
+#[test]
+fn map_links_traversed_exits() {
+    let mut map = Map::new();
+    feed(&mut map, FOOTHILLS);
+    map.leave("doorway");
+    feed(&mut map, DARK_CAVE);
+
+    assert_eq!(map.current().unwrap().name, "Dark cave");
+    assert_eq!(map.current().unwrap().exits, ["south"]);
+    let foothills = map.room("Foothills").unwrap();
+    let dark_cave = map.current().unwrap().fingerprint();
+    assert_eq!(foothills.traversed["doorway"], dark_cave);
+    assert_eq!(foothills.unexplored().collect::<Vec<_>>(), ["south"]);
+
+    // coming back keeps what was learned about the room
+    map.leave("south");
+    feed(&mut map, FOOTHILLS);
+    assert_eq!(map.rooms().len(), 2);
+    assert_eq!(map.current().unwrap().traversed.len(), 1);
+    assert_eq!(
+        map.room("Dark cave").unwrap().traversed["south"],
+        map.current().unwrap().fingerprint()
+    );
+}
+
+#[test]
+fn map_ignores_failed_moves() {
+    let mut map = Map::new();
+    feed(&mut map, FOOTHILLS);
+    map.leave("west");
+    feed(
+        &mut map,
+        "I don't understand; try 'help' for instructions.\n\nWhat do you do?\n",
+    );
+    feed(&mut map, DARK_CAVE);
+
+    assert!(map.room("Foothills").unwrap().traversed.is_empty());
+}
+
+const BRIDGE: &str = "\
+== Rope bridge ==
+This rope bridge creaks as you walk along it.
+
+There are 2 exits:
+- continue
+- back
+
+What do you do?
+";
+
+#[test]
+fn path_to_walks_through_known_rooms() {
+    let mut map = Map::new();
+    feed(&mut map, FOOTHILLS);
+    map.leave("doorway");
+    feed(&mut map, DARK_CAVE);
+    map.leave("north");
+    feed(&mut map, BRIDGE);
+    map.leave("back");
+    feed(&mut map, DARK_CAVE);
+    map.leave("south");
+    feed(&mut map, FOOTHILLS);
+
+    assert_eq!(map.path_to("Foothills").unwrap(), Vec::<String>::new());
+    assert_eq!(map.path_to("Rope bridge").unwrap(), ["doorway", "north"]);
+}
+
+#[test]
+fn path_to_needs_a_known_route() {
+    let mut map = Map::new();
+    assert_eq!(map.path_to("Foothills"), None);
+
+    feed(&mut map, FOOTHILLS);
+    map.leave("doorway");
+    feed(&mut map, DARK_CAVE);
+    assert_eq!(map.path_to("Vault"), None);
+    // the cave's way back south was never taken
+    assert_eq!(map.path_to("Foothills"), None);
+}
+
+const FAILED_MOVE: &str = "I don't understand; try 'help' for instructions.\n\nWhat do you do?\n";
+
+#[test]
+fn explore_step_heads_for_the_nearest_unexplored_exit() {
+    let mut map = Map::new();
+    let mut commands: Vec<u8> = vec![];
+    assert!(map.explore_step(&mut commands));
+    feed(&mut map, FOOTHILLS);
+    // the last exit first, it tends to be the way back
+    assert!(map.explore_step(&mut commands));
+    feed(&mut map, FAILED_MOVE);
+    assert!(map.explore_step(&mut commands));
+    feed(&mut map, BRIDGE);
+    assert!(map.explore_step(&mut commands));
+    feed(&mut map, FOOTHILLS);
+    // nothing left here, the bridge's other exit is one step away
+    assert!(map.explore_step(&mut commands));
+    feed(&mut map, BRIDGE);
+    assert!(map.explore_step(&mut commands));
+
+    assert_eq!(
+        String::from_utf8(commands).unwrap(),
+        "look\ngo south\ngo doorway\ngo back\ngo doorway\ngo continue\n"
+    );
+    assert_eq!(
+        map.room("Foothills").unwrap().traversed["doorway"],
+        map.room("Rope bridge").unwrap().fingerprint()
+    );
+}
+
+#[test]
+fn explore_step_stops_when_everything_is_explored() {
+    let mut map = Map::new();
+    feed(&mut map, DARK_CAVE);
+    map.leave("south");
+    feed(&mut map, FOOTHILLS);
+    map.leave("south");
+    feed(&mut map, FAILED_MOVE);
+    map.leave("doorway");
+    feed(&mut map, DARK_CAVE);
+
+    let mut commands: Vec<u8> = vec![];
+    assert!(!map.explore_step(&mut commands));
+    assert!(commands.is_empty());
+}
+
+#[test]
+fn explore_step_avoids_deadly_exits() {
+    let mut map = Map::new();
+    feed(&mut map, FOOTHILLS);
+    map.leave("doorway");
+    map.record_death();
+    assert!(map.current().is_none());
+    feed(&mut map, FOOTHILLS);
+
+    let mut commands: Vec<u8> = vec![];
+    map.explore_step(&mut commands);
+    assert_eq!(String::from_utf8(commands).unwrap(), "go south\n");
+}
+
+#[test]
+fn explore_walks_until_nothing_is_left() {
+    let (reader, mut writer) = std::io::pipe().unwrap();
+    writer.write_all(FOOTHILLS.as_bytes()).unwrap();
+    writer.write_all(b"Taken.\nWhat do you do?\n").unwrap();
+    writer.write_all(FAILED_MOVE.as_bytes()).unwrap();
+    writer.write_all(DARK_CAVE.as_bytes()).unwrap();
+    writer.write_all(FOOTHILLS.as_bytes()).unwrap();
+    // never read, exploring is over by then
+    writer.write_all(FOOTHILLS.as_bytes()).unwrap();
+    drop(writer);
+
+    let mut commands: Vec<u8> = vec![];
+    let playthrough = explore(reader, &mut commands);
+    assert_eq!(
+        String::from_utf8(commands).unwrap(),
+        "take tablet\ngo south\ngo doorway\ngo south\n"
+    );
+    assert_eq!(playthrough.map.rooms().len(), 2);
+}
+
+const MAZE_LADDER: &str = "\
+== Twisty passages ==
+You are in a maze of twisty little passages, all dimly lit by more bioluminescent moss.
+
+There are 3 exits:
+- ladder
+- north
+- west
+
+What do you do?
+";
+
+const MAZE_ALIKE: &str = "\
+== Twisty passages ==
+You are in a little maze of twisty passages, all alike.
+
+There are 2 exits:
+- north
+- east
+
+What do you do?
+";
+
+const MAZE_EXIT: &str = "\
+== Twisty passages ==
+You are in a little maze of twisty passages, all alike.
+
+There are 3 exits:
+- north
+- east
+- south
+
+What do you do?
+";
+
+#[test]
+fn map_tells_apart_rooms_that_share_a_name() {
+    let mut map = Map::new();
+    feed(&mut map, MAZE_LADDER);
+    map.leave("west");
+    feed(&mut map, MAZE_ALIKE);
+    map.leave("north");
+    feed(&mut map, MAZE_EXIT);
+
+    // same name, and the last two have the same description too
+    assert_eq!(map.rooms().len(), 3);
+    let fingerprints: HashSet<String> = map
+        .rooms()
+        .values()
+        .map(|room| room.fingerprint())
+        .collect();
+    assert_eq!(fingerprints.len(), 3);
+    assert_eq!(
+        map.path_to("Twisty passages").unwrap(),
+        Vec::<String>::new()
+    );
+}
+
+#[test]
+fn explore_step_escapes_loops_in_the_maze() {
+    let mut map = Map::new();
+    let mut commands: Vec<u8> = vec![];
+    feed(&mut map, MAZE_LADDER);
+    map.leave("ladder");
+    feed(&mut map, FAILED_MOVE);
+
+    // the alike room's east loops around and north comes right back
+    assert!(map.explore_step(&mut commands));
+    feed(&mut map, MAZE_ALIKE);
+    assert!(map.explore_step(&mut commands));
+    feed(&mut map, MAZE_LADDER);
+    assert!(map.explore_step(&mut commands));
+    feed(&mut map, MAZE_LADDER);
+    // the only fresh exit left is the alike room's north, one step away
+    assert!(map.explore_step(&mut commands));
+    feed(&mut map, MAZE_ALIKE);
+    assert!(map.explore_step(&mut commands));
+    feed(&mut map, MAZE_EXIT);
+
+    assert_eq!(
+        String::from_utf8(commands).unwrap(),
+        "go west\ngo east\ngo north\ngo west\ngo north\n"
+    );
+    assert_eq!(map.current().unwrap().exits.len(), 3);
+}
+
+#[test]
+fn send_command_uses_the_matching_controller_method() {
+    let mut commands: Vec<u8> = vec![];
+    for command in ["go north", "take red coin", "inv", "look red coin"] {
+        send_command(&mut commands, command);
+    }
+    assert_eq!(
+        String::from_utf8(commands).unwrap(),
+        "go north\ntake red coin\ninv\nlook red coin\n"
+    );
+}
+
+#[test]
+fn replay_sends_one_command_per_prompt() {
+    let (reader, mut writer) = std::io::pipe().unwrap();
+    writer
+        .write_all(b"== Foothills ==\nWhat do you do?\nTaken.\nWhat do you do?\nWhat do you do?\n")
+        .unwrap();
+    drop(writer);
+
+    let mut commands: Vec<u8> = vec![];
+    replay(
+        reader,
+        &mut commands,
+        "take tablet\n\nuse tablet\n",
+        &b"inv\n"[..],
+    );
+
+    assert_eq!(
+        String::from_utf8(commands).unwrap(),
+        "take tablet\nuse tablet\ninv\n"
+    );
+}
+
+#[test]
+fn interactive_forwards_input_lines() {
+    let (output, mut game) = std::io::pipe().unwrap();
+    let (mut commands, controller) = std::io::pipe().unwrap();
+    game.write_all(b"What do you do?\n").unwrap();
+    drop(game);
+
+    interactive(
+        output,
+        controller,
+        std::io::Cursor::new("go north\ntake lantern\n"),
+    );
+
+    let mut sent = String::new();
+    commands.read_to_string(&mut sent).unwrap();
+    assert_eq!(sent, "go north\ntake lantern\n");
+}
+
+#[test]
+fn transcript_interleaves_output_and_commands() {
+    let (reader, mut writer) = std::io::pipe().unwrap();
+    writer
+        .write_all(b"== Foothills ==\nWhat do you do?\nTaken.\nWhat do you do?\n")
+        .unwrap();
+    drop(writer);
+
+    let mut commands: Vec<u8> = vec![];
+    let mut log: Vec<u8> = vec![];
+    let transcript = Transcript::new(&mut commands, &mut log);
+    replay(reader, transcript, "take tablet\nuse tablet\n", &b""[..]);
+
+    assert_eq!(
+        String::from_utf8(commands).unwrap(),
+        "take tablet\nuse tablet\n"
+    );
+    assert_eq!(
+        String::from_utf8(log).unwrap(),
+        "== Foothills ==\nWhat do you do?\n[turn 1] > take tablet\n\
+         Taken.\nWhat do you do?\n[turn 2] > use tablet\n"
+    );
+}
+
+#[test]
+fn find_codes_picks_out_code_like_words() {
+    let lines = [
+        "this one into the challenge website: BxReqOXrTUEc",
+        "You find yourself writing \"QrbRkEqNNXmi\" on the tablet.",
+        "    qivDHMIoZxlK",
+        "Unfortunately, the passage successfully collapsed.",
+        "Somewhere123 ABCDEFGHIJKL abcdefghijkl",
+    ];
+    let codes: Vec<&str> = lines.iter().flat_map(|line| find_codes(line)).collect();
+    assert_eq!(codes, ["BxReqOXrTUEc", "QrbRkEqNNXmi", "qivDHMIoZxlK"]);
+}
+
+#[test]
+fn solve_collects_each_code_once() {
+    let (reader, mut writer) = std::io::pipe().unwrap();
+    writer
+        .write_all(b"The self-test completion code is: wbMJMdiFOPKz\nwbMJMdiFOPKz\n")
+        .unwrap();
+    drop(writer);
+
+    let playthrough = solve(reader, Vec::new());
+    assert_eq!(playthrough.codes, ["wbMJMdiFOPKz"]);
+}
+
+struct Recovering<'a> {
+    commands: &'a mut Vec<u8>,
+    deaths: &'a mut Vec<String>,
+}
+
+impl Controller for Recovering<'_> {
+    fn help(&mut self) {}
+    fn look(&mut self) {}
+    fn inv(&mut self) {}
+    fn go_place(&mut self, place: &str) {
+        self.command(&format!("go {place}"));
+    }
+    fn take_thing(&mut self, thing: &str) {
+        self.command(&format!("take {thing}"));
+    }
+    fn drop_thing(&mut self, _thing: &str) {}
+    fn use_thing(&mut self, _thing: &str) {}
+    fn command(&mut self, command: &str) {
+        self.commands.extend(format!("{command}\n").bytes());
+    }
+}
+
+impl Observer for Recovering<'_> {
+    fn on_death(&mut self, line: &str) -> Vec<String> {
+        self.deaths.push(line.to_string());
+        vec!["go doorway".to_string(), "go north".to_string()]
+    }
+}
+
+#[test]
+fn solve_starts_over_after_dying() {
+    let (reader, mut writer) = std::io::pipe().unwrap();
+    writer.write_all(FOOTHILLS.as_bytes()).unwrap();
+    writer
+        .write_all(
+            b"You have been eaten by a grue.\nWhat do you do?\nWhat do you do?\nWhat do you do?\n",
+        )
+        .unwrap();
+    drop(writer);
+
+    let mut commands = vec![];
+    let mut deaths = vec![];
+    let controller = Recovering {
+        commands: &mut commands,
+        deaths: &mut deaths,
+    };
+    let playthrough = solve(reader, controller);
+
+    assert_eq!(playthrough.deaths, 1);
+    assert_eq!(deaths, ["You have been eaten by a grue."]);
+    // the tablet seen before dying is not taken again afterwards
+    assert_eq!(
+        String::from_utf8(commands).unwrap(),
+        "take tablet\ngo doorway\ngo north\n"
+    );
+    assert!(playthrough.map.current().is_none());
+    assert!(playthrough.map.room("Foothills").is_some());
+}
+
+#[test]
+fn solve_takes_each_thing_once() {
+    let (reader, mut writer) = std::io::pipe().unwrap();
+    writer.write_all(FOOTHILLS.as_bytes()).unwrap();
+    writer.write_all(b"Taken.\nWhat do you do?\n").unwrap();
+    writer.write_all(FOOTHILLS.as_bytes()).unwrap();
+    writer.write_all(DARK_CAVE.as_bytes()).unwrap();
+    drop(writer);
+
+    let mut commands: Vec<u8> = vec![];
+    let playthrough = solve(reader, &mut commands);
+    assert_eq!(String::from_utf8(commands).unwrap(), "take tablet\n");
+    assert_eq!(playthrough.turns, 1);
+}
+
+#[test]
+fn solve_gives_up_on_things_that_are_not_there() {
+    let (reader, mut writer) = std::io::pipe().unwrap();
+    writer.write_all(FOOTHILLS.as_bytes()).unwrap();
+    writer
+        .write_all(b"You see no such item here.\nWhat do you do?\nWhat do you do?\n")
+        .unwrap();
+    drop(writer);
+
+    let mut commands: Vec<u8> = vec![];
+    solve(reader, &mut commands);
+    assert_eq!(String::from_utf8(commands).unwrap(), "take tablet\n");
+}
+
+// walks through every exit it hears about, as soon as it hears about it
+struct Wanderer<'a> {
+    commands: &'a mut Vec<u8>,
+    exits: bool,
+}
+
+impl Controller for Wanderer<'_> {
+    fn help(&mut self) {}
+    fn look(&mut self) {}
+    fn inv(&mut self) {}
+    fn go_place(&mut self, place: &str) {
+        self.commands.extend(format!("go {place}\n").bytes());
+    }
+    fn take_thing(&mut self, _thing: &str) {}
+    fn drop_thing(&mut self, _thing: &str) {}
+    fn use_thing(&mut self, _thing: &str) {}
+    fn command(&mut self, _command: &str) {}
+}
+
+impl Observer for Wanderer<'_> {
+    fn on_output(&mut self, line: &str) {
+        if line.starts_with("There ") {
+            self.exits = true;
+        } else if line.is_empty() {
+            self.exits = false;
+        } else if let Some(exit) = line.strip_prefix("- ")
+            && self.exits
+        {
+            self.go_place(exit);
+        }
+    }
+}
+
+#[test]
+fn observers_can_react_to_output() {
+    let (reader, mut writer) = std::io::pipe().unwrap();
+    writer.write_all(DARK_CAVE.as_bytes()).unwrap();
+    drop(writer);
+
+    let mut commands = vec![];
+    let wanderer = Wanderer {
+        commands: &mut commands,
+        exits: false,
+    };
+    solve(reader, wanderer);
+    assert_eq!(String::from_utf8(commands).unwrap(), "go south\n");
+}
+
+#[test]
+fn solve_looks_at_coins_and_places_them() {
+    let (reader, mut writer) = std::io::pipe().unwrap();
+    writer
+        .write_all(
+            b"== Ruins ==\n\
+              There is a strange monument.  It reads:\n\
+              _ + _ * _^2 + _^3 - _ = 399\n\n\
+              Things of interest here:\n\
+              - red coin\n- corroded coin\n- shiny coin\n- concave coin\n- blue coin\n\n\
+              What do you do?\n\
+              Taken.\nTaken.\nTaken.\nTaken.\nTaken.\nWhat do you do?\n\
+              It has nine dots on one side.\n\
+              It has seven dots on one side.\n\
+              It has a triangle on one side.\n\
+              It has two dots on one side.\n\
+              It has a pentagon on one side.\n\
+              What do you do?\n",
+        )
+        .unwrap();
+    drop(writer);
+
+    let mut commands: Vec<u8> = vec![];
+    solve(reader, &mut commands);
+
+    let commands = String::from_utf8(commands).unwrap();
+    let commands: Vec<&str> = commands.lines().collect();
+    assert_eq!(
+        commands[5..],
+        [
+            "look blue coin",
+            "look concave coin",
+            "look corroded coin",
+            "look red coin",
+            "look shiny coin",
+            "use blue coin",
+            "use red coin",
+            "use shiny coin",
+            "use concave coin",
+            "use corroded coin",
+        ]
+    );
+}
+
+#[test]
+fn solve_vault_finds_shortest_walk_to_thirty() {
+    let path = solve_vault(&VAULT_GRID).expect("vault should be solvable");
+    assert_eq!(path.len(), 12);
+
+    // walk the path again and weigh the orb at every number room
+    let (mut row, mut col, mut weight, mut operator) = (0usize, 0usize, 22u16, None);
+    for direction in &path {
+        match *direction {
+            "north" => row += 1,
+            "south" => row -= 1,
+            "east" => col += 1,
+            "west" => col -= 1,
+            other => panic!("unexpected direction {other}"),
+        }
+        assert_ne!((row, col), (0, 0));
+        match (VAULT_GRID[row][col], operator.take()) {
+            (VaultRoom::Number(n), Some(VaultRoom::Add)) => weight += n,
+            (VaultRoom::Number(n), Some(VaultRoom::Sub)) => weight -= n,
+            (VaultRoom::Number(n), Some(VaultRoom::Mul)) => weight *= n,
+            (room, _) => operator = Some(room),
+        }
+    }
+    assert_eq!((row, col, weight), (3, 3, 30));
+}
+
+#[test]
+fn solve_vault_gives_up_on_impossible_grid() {
+    // with only multiplication the orb never gets lighter than 22 or stops
+    // being a multiple of it
+    let grid = VAULT_GRID.map(|row| {
+        row.map(|room| match room {
+            VaultRoom::Number(n) => VaultRoom::Number(n),
+            _ => VaultRoom::Mul,
+        })
+    });
+    assert_eq!(solve_vault(&grid), None);
+}
+
+#[test]
+fn inventory_reads_listings() {
+    let mut inventory = Inventory::new();
+    assert!(!inventory.read_line("Your inventory:"));
+    assert!(!inventory.read_line("- tablet"));
+    assert!(!inventory.read_line("- lit  lantern."));
+    assert!(inventory.read_line(""));
+    assert!(inventory.contains("tablet") && inventory.contains("lit lantern"));
+    assert_eq!(inventory.items().len(), 2);
+
+    // lines that aren't part of a listing change nothing
+    assert!(!inventory.read_line("- north"));
+    assert_eq!(inventory.items().len(), 2);
+
+    assert!(!inventory.read_line("Your inventory:"));
+    assert!(inventory.read_line("What do you do?"));
+    assert!(inventory.items().is_empty());
+
+    inventory.read_line("Your inventory:");
+    inventory.read_line("- tablet");
+    inventory.read_line("");
+    assert!(inventory.read_line("Your inventory is empty."));
+    assert!(inventory.items().is_empty());
+}
+
+#[test]
+fn solve_checks_inventory_after_dying() {
+    let (reader, mut writer) = std::io::pipe().unwrap();
+    writer.write_all(FOOTHILLS.as_bytes()).unwrap();
+    writer
+        .write_all(b"Taken.\nYou have been eaten by a grue.\nWhat do you do?\n")
+        .unwrap();
+    writer
+        .write_all(b"Your inventory:\n- tablet\n\nWhat do you do?\n")
+        .unwrap();
+    writer.write_all(FOOTHILLS.as_bytes()).unwrap();
+    drop(writer);
+
+    let mut commands: Vec<u8> = vec![];
+    let playthrough = solve(reader, &mut commands);
+    // the listing says the tablet survived, so it isn't taken again
+    assert_eq!(String::from_utf8(commands).unwrap(), "take tablet\ninv\n");
+    assert!(playthrough.inventory.contains("tablet"));
+}
+
+const PITCH_BLACK: &str = "\
+== Passage ==
+It is pitch black.  You are likely to be eaten by a grue.
+
+There are 2 exits:
+- continue
+- back
+
+What do you do?
+";
+
+#[test]
+fn darkness_is_recognized() {
+    assert!(is_darkness(
+        "It is pitch black.  You are likely to be eaten by a grue."
+    ));
+    assert!(is_darkness("== Fumbling around in the darkness =="));
+    assert!(!is_darkness("This is a dark, narrow passage."));
+}
+
+#[test]
+fn solve_lights_the_lantern_in_the_dark() {
+    let (reader, mut writer) = std::io::pipe().unwrap();
+    writer
+        .write_all(b"Your inventory:\n- lantern\n\nWhat do you do?\n")
+        .unwrap();
+    writer.write_all(PITCH_BLACK.as_bytes()).unwrap();
+    writer
+        .write_all(b"You light your lantern.\nWhat do you do?\n")
+        .unwrap();
+    drop(writer);
+
+    let mut commands: Vec<u8> = vec![];
+    solve(reader, &mut commands);
+    assert_eq!(String::from_utf8(commands).unwrap(), "use lantern\ninv\n");
+}
+
+#[test]
+fn solve_backs_out_of_the_dark_without_a_lantern() {
+    let (reader, mut writer) = std::io::pipe().unwrap();
+    writer.write_all(PITCH_BLACK.as_bytes()).unwrap();
+    writer.write_all(b"What do you do?\n").unwrap();
+    drop(writer);
+
+    let mut commands: Vec<u8> = vec![];
+    let playthrough = solve(reader, &mut commands);
+    assert_eq!(String::from_utf8(commands).unwrap(), "go back\n");
+    assert_eq!(playthrough.deaths, 0);
+}
+
+#[test]
+fn explore_does_not_go_back_into_the_dark() {
+    let (reader, mut writer) = std::io::pipe().unwrap();
+    writer.write_all(BRIDGE.as_bytes()).unwrap();
+    writer.write_all(FAILED_MOVE.as_bytes()).unwrap();
+    writer.write_all(PITCH_BLACK.as_bytes()).unwrap();
+    writer.write_all(BRIDGE.as_bytes()).unwrap();
+    // never read, the passage can't be explored without a light
+    writer.write_all(PITCH_BLACK.as_bytes()).unwrap();
+    drop(writer);
+
+    let mut commands: Vec<u8> = vec![];
+    let playthrough = explore(reader, &mut commands);
+    assert_eq!(
+        String::from_utf8(commands).unwrap(),
+        "go back\ngo continue\ngo back\n"
+    );
+    let bridge = playthrough.map.room("Rope bridge").unwrap();
+    assert_eq!(bridge.traversed.len(), 1);
+}
+
+const FUMBLING: &str = "\
+== Fumbling around in the darkness ==
+Without a source of light, you have become hopelessly lost and are fumbling around in the darkness.
+
+There are 2 exits:
+- forward
+- back
+
+What do you do?
+";
+
+#[test]
+fn retreat_undoes_the_move_into_the_room() {
+    let mut map = Map::new();
+    assert_eq!(map.retreat(), None);
+
+    // the cave's south exit is known to lead back
+    feed(&mut map, FOOTHILLS);
+    map.leave("doorway");
+    feed(&mut map, DARK_CAVE);
+    map.leave("south");
+    feed(&mut map, FOOTHILLS);
+    map.leave("doorway");
+    feed(&mut map, DARK_CAVE);
+    assert_eq!(map.retreat().as_deref(), Some("south"));
+    feed(&mut map, FOOTHILLS);
+
+    // nothing is known about the dark, but doorway has no opposite direction
+    map.leave("doorway");
+    feed(&mut map, PITCH_BLACK);
+    assert_eq!(map.retreat().as_deref(), Some("back"));
+    feed(&mut map, FOOTHILLS);
+
+    // west would undo going east, "back" leads somewhere else
+    map.leave("south");
+    feed(&mut map, FUMBLING);
+    assert_eq!(map.retreat(), None);
+}
+
+#[test]
+fn solve_fills_and_lights_the_lantern() {
+    let (reader, mut writer) = std::io::pipe().unwrap();
+    writer
+        .write_all(
+            b"\
+== Moss cavern ==
+You are standing in a large cavern full of bioluminescent moss.
+
+Things of interest here:
+- empty lantern
+- can
+
+There is 1 exit:
+- west
+
+What do you do?
+",
+        )
+        .unwrap();
+    writer.write_all(b"Taken.\n\nWhat do you do?\n").unwrap();
+    writer.write_all(b"Taken.\n\nWhat do you do?\n").unwrap();
+    writer
+        .write_all(b"You fill your lantern with oil.  It seems to cheer up!\n\nWhat do you do?\n")
+        .unwrap();
+    writer
+        .write_all(
+            b"You light your lantern.\n\
+== Moss cavern ==
+You are standing in a large cavern full of bioluminescent moss.
+
+There is 1 exit:
+- west
+
+What do you do?
+",
+        )
+        .unwrap();
+    writer
+        .write_all(b"Your inventory:\n- lit lantern\n\nWhat do you do?\n")
+        .unwrap();
+    drop(writer);
+
+    let mut commands: Vec<u8> = vec![];
+    let playthrough = solve(reader, &mut commands);
+    assert_eq!(
+        String::from_utf8(commands).unwrap(),
+        "take empty lantern\ntake can\nuse can\nuse lantern\ninv\n"
+    );
+    assert!(playthrough.inventory.items().contains("lit lantern"));
+}
+
+const GRUE_PASSAGE: &str = "\
+== Twisty passages ==
+You are in a maze of alike little passages, all twisty.
+
+The passage to the east looks very dark; you think you hear a Grue.
+
+There are 4 exits:
+- north
+- south
+- west
+- east
+
+What do you do?
+";
+
+#[test]
+fn map_reads_darkness_warnings() {
+    let mut map = Map::new();
+    feed(&mut map, GRUE_PASSAGE);
+    let room = map.current().unwrap();
+    assert!(room.warns_of_darkness("east"));
+    assert!(!room.warns_of_darkness("west"));
+    assert!(!room.is_dark());
+    assert_eq!(
+        map.explorable(room).collect::<Vec<_>>(),
+        ["north", "south", "west"]
+    );
+
+    map.set_light(true);
+    let room = map.current().unwrap();
+    assert_eq!(map.explorable(room).count(), 4);
+}
+
+// the grue passage used to lead into the fumbling rooms, which go nowhere but
+// deeper, and exploring stopped there for good
+#[test]
+fn explore_keeps_out_of_the_grue_passage_without_a_light() {
+    let (reader, mut writer) = std::io::pipe().unwrap();
+    writer.write_all(GRUE_PASSAGE.as_bytes()).unwrap();
+    writer.write_all(FAILED_MOVE.as_bytes()).unwrap();
+    writer.write_all(FAILED_MOVE.as_bytes()).unwrap();
+    writer.write_all(FAILED_MOVE.as_bytes()).unwrap();
+    // never read, east is the only exit left
+    writer.write_all(FUMBLING.as_bytes()).unwrap();
+    drop(writer);
+
+    let mut commands: Vec<u8> = vec![];
+    let playthrough = explore(reader, &mut commands);
+    assert_eq!(
+        String::from_utf8(commands).unwrap(),
+        "go west\ngo south\ngo north\n"
+    );
+    assert!(
+        playthrough
+            .map
+            .room("Fumbling around in the darkness")
+            .is_none()
+    );
+}
+
+const NARROW_PASSAGE: &str = "\
+== Dark passage ==
+You are in a dark, narrow passage.
+
+There are 2 exits:
+- east
+- west
+
+What do you do?
+";
+
+#[test]
+fn explore_step_walks_through_rooms_that_look_the_same() {
+    let mut map = Map::new();
+    feed(&mut map, NARROW_PASSAGE);
+    map.leave("west");
+    feed(&mut map, NARROW_PASSAGE);
+
+    // both ways seem to come right back, but they're worth another try, the
+    // one tried less often first
+    let mut commands: Vec<u8> = vec![];
+    assert!(map.explore_step(&mut commands));
+    feed(&mut map, NARROW_PASSAGE);
+    assert!(map.explore_step(&mut commands));
+    feed(&mut map, NARROW_PASSAGE);
+    assert!(map.explore_step(&mut commands));
+    feed(&mut map, FOOTHILLS);
+
+    assert_eq!(
+        String::from_utf8(commands).unwrap(),
+        "go east\ngo east\ngo west\n"
+    );
+    assert_eq!(map.current().unwrap().name, "Foothills");
+}
+
+#[test]
+fn explore_step_tries_locked_doors_again_once_unblocked() {
+    let mut map = Map::new();
+    feed(&mut map, DARK_CAVE);
+    map.leave("south");
+    feed(&mut map, "That door is locked.\n\nWhat do you do?\n");
+
+    let mut commands: Vec<u8> = vec![];
+    assert!(!map.explore_step(&mut commands));
+    map.unblock();
+    assert!(map.explore_step(&mut commands));
+    assert_eq!(String::from_utf8(commands).unwrap(), "go south\n");
+}
+
+#[test]
+fn step_toward_takes_the_first_exit_of_the_path() {
+    let mut map = Map::new();
+    let mut commands: Vec<u8> = vec![];
+    assert!(!map.step_toward(&mut commands, |_| true));
+
+    feed(&mut map, FOOTHILLS);
+    map.leave("doorway");
+    feed(&mut map, DARK_CAVE);
+    map.leave("south");
+    feed(&mut map, FOOTHILLS);
+    map.leave("doorway");
+    feed(&mut map, DARK_CAVE);
+
+    assert!(!map.step_toward(&mut commands, |room| room.name == "Rope bridge"));
+    assert!(!map.step_toward(&mut commands, |room| room.name == "Dark cave"));
+    assert!(map.step_toward(&mut commands, |room| room.name == "Foothills"));
+    assert_eq!(String::from_utf8(commands).unwrap(), "go south\n");
+}
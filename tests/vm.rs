@@ -0,0 +1,1049 @@
+use std::io::Read;
+use std::sync::atomic::Ordering;
+
+use vm_thingy::vm::{Error, HaltReason, Machine, Opcode, State};
+
+#[test]
+fn runs_program_through_public_api() {
+    // out 'A'; halt
+    let bin = [19, 0, 65, 0, 0, 0];
+    let (mut state, (mut stdout, _stdin)) = State::init_with(&bin);
+
+    while !state.done() {
+        state.next().unwrap();
+    }
+
+    let mut buf = [0; 1];
+    stdout.read_exact(&mut buf).unwrap();
+    assert_eq!(&buf, b"A");
+}
+
+#[test]
+fn register_accessors() {
+    let (mut state, _) = State::init_with(&[0, 0]);
+
+    assert_eq!(state.register(7), Some(0));
+    assert_eq!(state.register(8), None);
+
+    state.set_register(7, 32767).unwrap();
+    assert_eq!(state.register(7), Some(32767));
+
+    assert!(state.set_register(7, 32768).is_err());
+    assert!(state.set_register(8, 1).is_err());
+    assert_eq!(state.register(7), Some(32767));
+}
+
+#[test]
+fn peek_and_poke_words() {
+    let (mut state, _) = State::init_with(&[19, 0, 65, 0, 0, 0]);
+
+    assert_eq!(state.read_word(1).unwrap(), 65);
+    assert_eq!(state.read_word(32767).unwrap(), 0);
+    assert!(state.read_word(32768).is_err());
+
+    state.write_word(1, 66).unwrap();
+    assert_eq!(state.read_word(1).unwrap(), 66);
+
+    state.write_word(32767, 32768).unwrap();
+    assert_eq!(state.read_word(32767).unwrap(), 32768);
+    assert!(state.write_word(32768, 0).is_err());
+}
+
+#[test]
+fn invalid_instruction_is_an_error() {
+    let (mut state, _) = State::init_with(&[22, 0]);
+
+    assert!(matches!(
+        state.next(),
+        Err(vm_thingy::vm::Error::InvalidInstruction(22))
+    ));
+}
+
+#[test]
+fn program_counter_is_a_byte_offset() {
+    // noop; jmp 0
+    let (mut state, _) = State::init_with(&[21, 0, 6, 0, 0, 0]);
+
+    assert_eq!(state.program_counter(), 0);
+    state.next().unwrap();
+    assert_eq!(state.program_counter(), 2);
+    state.next().unwrap();
+    assert_eq!(state.program_counter(), 0);
+}
+
+#[test]
+fn step_reports_executed_opcode() {
+    // set r0 65; out r0; halt
+    let bin = [1, 0, 0, 128, 65, 0, 19, 0, 0, 128, 0, 0];
+    let (mut state, _out) = State::init_with(&bin);
+
+    assert_eq!(state.step().unwrap(), Opcode::Set);
+    assert_eq!(state.step().unwrap(), Opcode::Out);
+    assert_eq!(state.step().unwrap(), Opcode::Halt);
+    assert!(state.done());
+}
+
+#[test]
+fn cycles_count_executed_instructions() {
+    // noop; noop; halt
+    let (mut state, _) = State::init_with(&[21, 0, 21, 0, 0, 0]);
+
+    while !state.done() {
+        state.next().unwrap();
+    }
+    assert_eq!(state.cycles(), 3);
+
+    state.next().unwrap();
+    assert_eq!(state.cycles(), 3);
+
+    state.reset();
+    assert_eq!(state.cycles(), 0);
+}
+
+#[test]
+fn opcode_counts_track_each_opcode() {
+    // noop; noop; halt
+    let (mut state, _) = State::init_with(&[21, 0, 21, 0, 0, 0]);
+
+    while !state.done() {
+        state.next().unwrap();
+    }
+
+    let counts = state.opcode_counts();
+    assert_eq!(counts[Opcode::Noop as usize], 2);
+    assert_eq!(counts[Opcode::Halt as usize], 1);
+    assert_eq!(counts.iter().sum::<u64>(), 3);
+
+    state.reset();
+    assert_eq!(state.opcode_counts(), [0; 22]);
+}
+
+#[test]
+fn mult_is_modulo_32768() {
+    // mult r0 32767 32767; halt
+    let bin = [10, 0, 0, 128, 255, 127, 255, 127, 0, 0];
+    let (mut state, _) = State::init_with(&bin);
+
+    while !state.done() {
+        state.next().unwrap();
+    }
+
+    assert_eq!(state.register(0), Some(1));
+}
+
+#[test]
+fn mod_by_zero_is_an_error() {
+    // mod r0 5 0
+    let bin = [11, 0, 0, 128, 5, 0, 0, 0];
+    let (mut state, _) = State::init_with(&bin);
+
+    assert!(matches!(
+        state.next(),
+        Err(vm_thingy::vm::Error::DivideByZero)
+    ));
+}
+
+#[test]
+fn reset_restores_initial_state() {
+    // set r0 1; wmem 0 0; halt
+    let bin = [1, 0, 0, 128, 1, 0, 16, 0, 0, 0, 0, 0, 0, 0];
+    let (mut state, _) = State::init_with(&bin);
+
+    while !state.done() {
+        state.next().unwrap();
+    }
+    assert_eq!(state.register(0), Some(1));
+
+    state.reset();
+    assert_eq!(state.program_counter(), 0);
+    assert_eq!(state.register(0), Some(0));
+    assert_eq!(state.read_word(0).unwrap(), 1);
+}
+
+#[test]
+fn reset_registers_keeps_patched_ram() {
+    let bin = vm_thingy::asm::assemble(
+        "push 3
+set r0 1
+out 65
+halt",
+    )
+    .unwrap();
+    let (mut state, _out) = State::init_with(&bin);
+
+    state.next().unwrap();
+    state.next().unwrap();
+    // turn the out into a noop
+    state.write_word(5, 21).unwrap();
+    state.write_word(6, 21).unwrap();
+
+    state.reset_registers();
+    assert_eq!(state.program_counter(), 0);
+    assert_eq!(state.register(0), Some(0));
+    assert!(state.stack().is_empty());
+    assert_eq!(state.read_word(5).unwrap(), 21);
+    assert_eq!(state.cycles(), 2);
+
+    while !state.done() {
+        state.next().unwrap();
+    }
+    assert_eq!(state.opcode_counts()[Opcode::Noop as usize], 2);
+}
+
+#[test]
+fn unbounded_push_overflows_cleanly() {
+    // push 1; jmp 0
+    let bin = [2, 0, 1, 0, 6, 0, 0, 0];
+    let (mut state, _) = State::init_with(&bin);
+
+    let err = loop {
+        if let Err(err) = state.next() {
+            break err;
+        }
+    };
+
+    assert!(matches!(err, vm_thingy::vm::Error::StackOverflow));
+    assert_eq!(
+        state.opcode_counts()[Opcode::Push as usize],
+        u16::MAX as u64
+    );
+}
+
+#[test]
+fn reading_from_closed_input_is_an_error() {
+    // in r0
+    let bin = [20, 0, 0, 128];
+    let (mut state, (_stdout, stdin)) = State::init_with(&bin);
+    drop(stdin);
+
+    assert!(matches!(
+        state.next(),
+        Err(vm_thingy::vm::Error::InputClosed)
+    ));
+    assert_eq!(state.program_counter(), 0);
+}
+
+#[test]
+fn coverage_marks_executed_instruction_starts() {
+    // noop; jmp 3; halt; halt
+    let bin = [21, 0, 6, 0, 3, 0, 0, 0, 0, 0];
+    let (mut state, _) = State::init_with(&bin);
+
+    while !state.done() {
+        state.next().unwrap();
+    }
+
+    let covered: Vec<usize> = (0..state.coverage().len())
+        .filter(|&addr| state.coverage()[addr])
+        .collect();
+    assert_eq!(covered, vec![0, 2, 6]);
+
+    state.reset();
+    assert!(state.coverage().iter().all(|covered| !covered));
+}
+
+#[test]
+fn restore_rewinds_to_snapshot() {
+    let bin = vm_thingy::asm::assemble(
+        "
+        push 1
+        push 2
+        wmem 100 5
+        pop r0
+        set r1 9
+        halt
+        ",
+    )
+    .unwrap();
+    let (mut state, _) = State::init_with(&bin);
+
+    for _ in 0..3 {
+        state.next().unwrap();
+    }
+    let snapshot = state.snapshot();
+
+    while !state.done() {
+        state.next().unwrap();
+    }
+    assert_eq!(state.register(0), Some(2));
+    state.write_word(100, 0).unwrap();
+
+    state.restore(&snapshot).unwrap();
+    assert_eq!(state.snapshot(), snapshot);
+    assert_eq!(state.read_word(100).unwrap(), 5);
+    assert_eq!(state.register(1), Some(0));
+
+    while !state.done() {
+        state.next().unwrap();
+    }
+    assert_eq!(state.register(0), Some(2));
+    assert_eq!(state.register(1), Some(9));
+}
+
+#[test]
+fn save_and_load_round_trip() {
+    let bin = vm_thingy::asm::assemble("push 7\nset r3 42\nwmem 100 5\nhalt").unwrap();
+    let (mut state, _) = State::init_with(&bin);
+
+    for _ in 0..3 {
+        state.next().unwrap();
+    }
+
+    let path = std::env::temp_dir().join(format!("vm-thingy-save-{}.bin", std::process::id()));
+    state.save_to(&path).unwrap();
+    let (mut loaded, _) = State::load_from(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+
+    assert_eq!(loaded.snapshot(), state.snapshot());
+
+    loaded.reset();
+    assert_eq!(loaded.read_word(100).unwrap(), 0);
+    assert_eq!(loaded.read_word(0).unwrap(), 2);
+}
+
+#[test]
+fn errors_box_into_dyn_error() {
+    let (mut state, _) = State::init_with(&[22, 0]);
+
+    let result: Result<(), Box<dyn std::error::Error>> = state.next().map_err(Into::into);
+    let err = result.unwrap_err();
+
+    assert_eq!(err.to_string(), "Invalid Instruction: 22");
+    assert!(err.source().is_none());
+}
+
+#[test]
+fn io_errors_carry_their_cause() {
+    // out 'A'; halt
+    let (mut state, (stdout, _stdin)) = State::init_with(&[19, 0, 65, 0, 0, 0]);
+    drop(stdout);
+
+    let err = loop {
+        if let Err(err) = state.next() {
+            break err;
+        }
+    };
+
+    assert!(err.to_string().starts_with("IO Error: "));
+    assert!(std::error::Error::source(&err).is_some());
+}
+
+#[test]
+fn builder_seeds_registers_and_stack() {
+    let bin = vm_thingy::asm::assemble("push r7\npush r7\npush r7\npop r0\nhalt").unwrap();
+    let (mut state, _) = State::builder(&bin)
+        .stack_size(1)
+        .register(7, 1234)
+        .build()
+        .unwrap();
+
+    assert_eq!(state.register(7), Some(1234));
+    while !state.done() {
+        state.next().unwrap();
+    }
+    assert_eq!(state.register(0), Some(1234));
+
+    assert!(State::builder(&bin).register(0, 32768).build().is_err());
+    assert!(matches!(
+        State::builder(&bin).register(9, 5).build(),
+        Err(Error::InvalidRegister { value: 9, at: None })
+    ));
+}
+
+#[test]
+fn builder_accepts_external_pipes() {
+    let (stdout_reader, stdout) = std::io::pipe().unwrap();
+    let (stdin, _stdin_writer) = std::io::pipe().unwrap();
+
+    let mut state = State::builder(&[19, 0, 65, 0, 0, 0])
+        .build_with_io(stdin, stdout)
+        .unwrap();
+    while !state.done() {
+        state.next().unwrap();
+    }
+
+    let mut buf = [0; 1];
+    (&stdout_reader).read_exact(&mut buf).unwrap();
+    assert_eq!(&buf, b"A");
+}
+
+#[test]
+fn runs_with_in_memory_io() {
+    let bin =
+        vm_thingy::asm::assemble("in r0\nadd r0 r0 1\nout r0\nin r0\nadd r0 r0 1\nout r0\nhalt")
+            .unwrap();
+    let output = SharedBuffer::default();
+    let mut state = State::with_io(&bin, std::io::Cursor::new(b"HA".to_vec()), output.clone());
+
+    while !state.done() {
+        state.next().unwrap();
+    }
+
+    assert_eq!(output.0.lock().unwrap().as_slice(), b"IB");
+}
+
+#[derive(Clone, Default)]
+struct SharedBuffer(std::sync::Arc<std::sync::Mutex<Vec<u8>>>);
+
+impl std::io::Write for SharedBuffer {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.lock().unwrap().write(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+// hands out queued bytes and reports WouldBlock instead of blocking when empty
+#[derive(Clone, Default)]
+struct NonBlockingInput(std::sync::Arc<std::sync::Mutex<std::collections::VecDeque<u8>>>);
+
+impl std::io::Read for NonBlockingInput {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        match self.0.lock().unwrap().pop_front() {
+            Some(byte) => {
+                buf[0] = byte;
+                Ok(1)
+            }
+            None => Err(std::io::ErrorKind::WouldBlock.into()),
+        }
+    }
+}
+
+#[test]
+fn run_for_yields_on_limit_input_and_halt() {
+    use vm_thingy::vm::RunOutcome;
+
+    let bin = vm_thingy::asm::assemble("noop\nnoop\nnoop\nin r0\nout r0\nhalt").unwrap();
+    let input = NonBlockingInput::default();
+    let output = SharedBuffer::default();
+    let mut state = State::with_io(&bin, input.clone(), output.clone());
+
+    assert_eq!(state.run_for(2).unwrap(), RunOutcome::Limit);
+    assert_eq!(state.run_for(100).unwrap(), RunOutcome::NeedInput);
+    assert_eq!(state.run_for(100).unwrap(), RunOutcome::NeedInput);
+    assert_eq!(state.cycles(), 3);
+
+    input.0.lock().unwrap().push_back(b'x');
+    assert_eq!(state.run_for(100).unwrap(), RunOutcome::Halted);
+    assert_eq!(output.0.lock().unwrap().as_slice(), b"x");
+}
+
+#[test]
+fn stack_pops_back_down_in_order() {
+    let bin = vm_thingy::asm::assemble(
+        "
+        set r0 1        ; 0
+        push r0         ; 3
+        add r0 r0 1     ; 5
+        eq r1 r0 1001   ; 9
+        jf r1 3         ; 13
+        pop r2          ; 16
+        jt r2 16        ; 18
+        ",
+    )
+    .unwrap();
+    let (mut state, _) = State::init_with(&bin);
+
+    let err = loop {
+        if let Err(err) = state.next() {
+            break err;
+        }
+    };
+
+    assert!(matches!(err, vm_thingy::vm::Error::EmptyStack));
+    assert_eq!(state.register(2), Some(1));
+    assert_eq!(state.opcode_counts()[Opcode::Pop as usize], 1000);
+}
+
+#[test]
+fn stack_shows_return_addresses() {
+    let bin = vm_thingy::asm::assemble(
+        "
+        push 9          ; 0
+        call 5          ; 2
+        halt            ; 4
+        call 7          ; 5
+        ret             ; 7
+        ",
+    )
+    .unwrap();
+    let (mut state, _) = State::init_with(&bin);
+    assert!(state.stack().is_empty());
+
+    state.next().unwrap();
+    state.next().unwrap();
+    state.next().unwrap();
+    assert_eq!(state.stack(), [9, 4, 7]);
+
+    state.next().unwrap();
+    assert_eq!(state.stack(), [9, 4]);
+}
+
+#[test]
+fn word_and_byte_addresses_convert() {
+    use vm_thingy::vm::{ByteAddr, WordAddr};
+
+    assert_eq!(WordAddr(0x1571).to_byte(), ByteAddr(0x2ae2));
+    assert_eq!(ByteAddr(0x2ae2).to_word(), WordAddr(0x1571));
+    assert_eq!(ByteAddr(7).to_word(), WordAddr(3));
+    assert_eq!(WordAddr(32767).to_byte(), ByteAddr(65534));
+
+    // jmp 3; halt; out 65; halt
+    let (mut state, _) = State::init_with(&[6, 0, 3, 0, 0, 0, 19, 0, 65, 0, 0, 0]);
+    state.next().unwrap();
+    assert_eq!(ByteAddr(state.program_counter()).to_word(), WordAddr(3));
+}
+
+#[test]
+fn literal_jump_targets_are_doubled() {
+    let bin =
+        vm_thingy::asm::assemble("jmp 5\nnoop\nnoop\nnoop\njt 1 9\nnoop\njf 0 0x7fff").unwrap();
+    let (mut state, _) = State::init_with(&bin);
+
+    state.next().unwrap();
+    assert_eq!(state.program_counter(), 10);
+    state.next().unwrap();
+    assert_eq!(state.program_counter(), 18);
+    state.next().unwrap();
+    assert_eq!(state.program_counter(), 0xfffe);
+}
+
+#[test]
+fn register_jump_targets_are_doubled() {
+    let bin = vm_thingy::asm::assemble(
+        "
+        set r0 8        ; 0
+        set r1 11       ; 3
+        jmp r0          ; 6
+        jt r0 r1        ; 8
+        set r2 0x7fff   ; 11
+        call r2         ; 14
+        ",
+    )
+    .unwrap();
+    let (mut state, _) = State::init_with(&bin);
+
+    state.next().unwrap();
+    state.next().unwrap();
+    state.next().unwrap();
+    assert_eq!(state.program_counter(), 16);
+    state.next().unwrap();
+    assert_eq!(state.program_counter(), 22);
+    state.next().unwrap();
+    state.next().unwrap();
+    assert_eq!(state.program_counter(), 0xfffe);
+    // the return address is still a word address
+    assert_eq!(state.stack(), [16]);
+}
+
+#[test]
+fn signals_while_waiting_for_input() {
+    use std::io::Write;
+    use std::sync::atomic::Ordering;
+    use std::time::{Duration, Instant};
+
+    let bin = vm_thingy::asm::assemble("in r0\nout r0\nhalt").unwrap();
+    let (mut state, (mut stdout, mut stdin)) = State::init_with(&bin);
+    let waiting = state.waiting_for_input();
+    assert!(!waiting.load(Ordering::Acquire));
+
+    let vm = std::thread::spawn(move || {
+        while !state.done() {
+            state.next().unwrap();
+        }
+    });
+
+    let start = Instant::now();
+    while !waiting.load(Ordering::Acquire) {
+        assert!(
+            start.elapsed() < Duration::from_secs(5),
+            "never waited for input"
+        );
+        std::thread::yield_now();
+    }
+
+    stdin.write_all(b"x").unwrap();
+    vm.join().unwrap();
+    assert!(!waiting.load(Ordering::Acquire));
+
+    let mut buf = [0; 1];
+    stdout.read_exact(&mut buf).unwrap();
+    assert_eq!(&buf, b"x");
+}
+
+#[test]
+fn output_hook_sees_bytes_as_they_are_printed() {
+    let bin = vm_thingy::asm::assemble("out 104\nout 105\nhalt").unwrap();
+    let (mut state, (mut stdout, _stdin)) = State::init_with(&bin);
+    let (sender, printed) = std::sync::mpsc::channel();
+    state.set_output_hook(Box::new(move |byte| sender.send(byte).unwrap()));
+
+    state.next().unwrap();
+    assert_eq!(printed.try_iter().collect::<Vec<u8>>(), b"h");
+
+    state.next().unwrap();
+    state.next().unwrap();
+    assert_eq!(printed.try_iter().collect::<Vec<u8>>(), b"i");
+
+    let mut buf = [0; 2];
+    stdout.read_exact(&mut buf).unwrap();
+    assert_eq!(&buf, b"hi");
+
+    state.clear_output_hook();
+    state.reset();
+    state.next().unwrap();
+    assert!(printed.try_iter().next().is_none());
+}
+
+#[test]
+#[should_panic(expected = "Binary of 65538 bytes does not fit into 65536 bytes of RAM")]
+fn oversized_binary_panics_up_front() {
+    State::init_with(&[0; 65538]);
+}
+
+#[test]
+fn empty_binary_resets_and_restores() {
+    let (mut state, _) = State::init_with(&[]);
+    let snapshot = state.snapshot();
+    state.reset();
+    state.restore(&snapshot).unwrap();
+    assert_eq!(state.read_word(0).unwrap(), 0);
+}
+
+#[test]
+fn run_with_input_collects_output() {
+    let bin = vm_thingy::asm::assemble("in r0\nadd r0 r0 1\nout r0\nin r0\nout r0\nhalt").unwrap();
+    let (mut state, _) = State::init_with(&bin);
+
+    assert_eq!(state.run_with_input(b"ab").unwrap(), b"bb");
+    assert!(state.done());
+}
+
+#[test]
+fn run_with_input_reports_running_out() {
+    let bin = vm_thingy::asm::assemble("out 104\nin r0\nin r0\nhalt").unwrap();
+    let (mut state, _) = State::init_with(&bin);
+
+    assert!(matches!(
+        state.run_with_input(b"x"),
+        Err(vm_thingy::vm::Error::InputExhausted)
+    ));
+    assert_eq!(state.register(0).unwrap(), b'x' as u16);
+}
+
+#[test]
+fn instruction_budget_stops_endless_loops() {
+    let bin = vm_thingy::asm::assemble("noop\njmp 0").unwrap();
+    let (mut state, _) = State::init_with(&bin);
+    state.set_instruction_budget(10);
+
+    assert!(matches!(
+        state.run_for(100),
+        Err(vm_thingy::vm::Error::BudgetExceeded)
+    ));
+    assert_eq!(state.cycles(), 10);
+    assert!(matches!(
+        state.next(),
+        Err(vm_thingy::vm::Error::BudgetExceeded)
+    ));
+
+    state.reset();
+    state.next().unwrap();
+    state.clear_instruction_budget();
+    state.run_for(100).unwrap();
+    assert_eq!(state.cycles(), 101);
+}
+
+#[test]
+fn instruction_budget_covers_the_upper_half_of_memory() {
+    let bin = vm_thingy::asm::assemble("jmp 20000").unwrap();
+    let (mut state, _) = State::init_with(&bin);
+    state.apply_patch(20000, &[6, 20000]).unwrap();
+    state.set_instruction_budget(10);
+
+    assert!(matches!(
+        state.run_for(100),
+        Err(vm_thingy::vm::Error::BudgetExceeded)
+    ));
+    assert_eq!(state.cycles(), 10);
+    assert!(!state.done());
+}
+
+#[test]
+fn halt_reason_tells_halt_from_empty_ret() {
+    let run = |source: &str| {
+        let bin = vm_thingy::asm::assemble(source).unwrap();
+        let (mut state, _) = State::init_with(&bin);
+        assert_eq!(*state.halt_reason(), HaltReason::Running);
+        while !state.done() {
+            if state.next().is_err() {
+                break;
+            }
+        }
+        state
+    };
+
+    assert_eq!(*run("noop\nhalt").halt_reason(), HaltReason::Halt);
+    assert_eq!(*run("call 2\nhalt\nret").halt_reason(), HaltReason::Halt);
+    assert_eq!(*run("noop\nret").halt_reason(), HaltReason::RetEmptyStack);
+    assert_eq!(
+        *run("pop r0").halt_reason(),
+        HaltReason::Error("Empty Stack".to_string())
+    );
+
+    let mut state = run("ret");
+    state.reset();
+    assert_eq!(*state.halt_reason(), HaltReason::Running);
+}
+
+#[test]
+fn halting_is_part_of_snapshots_and_undone_by_resets() {
+    let bin = vm_thingy::asm::assemble("noop\nret").unwrap();
+    let (mut state, _) = State::init_with(&bin);
+    let running = state.snapshot();
+    state.next().unwrap();
+    state.next().unwrap();
+    let halted = state.snapshot();
+
+    state.reset_registers();
+    assert!(!state.done());
+    assert_eq!(*state.halt_reason(), HaltReason::Running);
+
+    state.restore(&halted).unwrap();
+    assert!(state.done());
+    assert_eq!(*state.halt_reason(), HaltReason::RetEmptyStack);
+    state.restore(&running).unwrap();
+    assert!(!state.done());
+    assert_eq!(*state.halt_reason(), HaltReason::Running);
+}
+
+#[test]
+fn every_address_is_a_jump_target() {
+    // the upper half of the address space used to stand for a halted program
+    for target in [16384, 20000] {
+        let bin = vm_thingy::asm::assemble(&format!("jmp {target}")).unwrap();
+        let output = SharedBuffer::default();
+        let mut state = State::with_io(&bin, std::io::empty(), output.clone());
+        state.apply_patch(target, &[19, 66]).unwrap();
+
+        state.next().unwrap();
+        assert!(!state.done());
+        assert_eq!(*state.halt_reason(), HaltReason::Running);
+        state.next().unwrap();
+        state.next().unwrap();
+        assert!(state.done());
+        assert_eq!(*state.halt_reason(), HaltReason::Halt);
+        assert_eq!(state.program_counter(), (target + 2) * 2);
+        assert_eq!(output.0.lock().unwrap().as_slice(), b"B");
+    }
+}
+
+#[test]
+fn patches_apply_whole_or_not_at_all() {
+    let (mut state, _) = State::init_with(&[]);
+
+    state.apply_patch(10, &[1, 32768, 6, 18]).unwrap();
+    let words: Vec<u16> = (10..14)
+        .map(|addr| state.read_word(addr).unwrap())
+        .collect();
+    assert_eq!(words, [1, 32768, 6, 18]);
+
+    state.apply_patch(32766, &[5, 5]).unwrap();
+    assert!(matches!(
+        state.apply_patch(32765, &[7, 7, 7, 7]),
+        Err(vm_thingy::vm::Error::InvalidAddress {
+            value: 32768,
+            at: None
+        })
+    ));
+    assert_eq!(state.read_word(32765).unwrap(), 0);
+    assert_eq!(state.read_word(32766).unwrap(), 5);
+}
+
+#[test]
+fn peeks_whether_input_comes_next() {
+    let bin = vm_thingy::asm::assemble("out 62\nin r0\nhalt").unwrap();
+    let (mut state, _io) = State::init_with(&bin);
+
+    assert!(!state.peek_next_is_input());
+    state.next().unwrap();
+    assert!(state.peek_next_is_input());
+    assert_eq!(state.cycles(), 1);
+
+    // answers the in, so only the halt is left
+    state.run_with_input(b"x").unwrap();
+    assert!(state.done());
+    assert!(!state.peek_next_is_input());
+}
+
+#[test]
+fn fed_lines_are_read_before_stdin() {
+    let bin = vm_thingy::asm::assemble("in r0\nin r1\nin r2\nin r3\nhalt").unwrap();
+    let (mut state, (_stdout, mut stdin)) = State::init_with(&bin);
+    std::io::Write::write_all(&mut stdin, b"z").unwrap();
+    state.feed_line("ab");
+
+    while !state.done() {
+        state.next().unwrap();
+    }
+
+    let registers: Vec<u16> = (0..4).map(|idx| state.register(idx).unwrap()).collect();
+    assert_eq!(registers, [b'a', b'b', b'\n', b'z'].map(u16::from));
+}
+
+#[test]
+fn forks_run_independently() {
+    let bin = vm_thingy::asm::assemble("in r0\nadd r0 r0 1\nout r0\nhalt").unwrap();
+    let (mut state, _io) = State::init_with(&bin);
+    state.feed_line("a");
+    state.next().unwrap();
+
+    let mut clone = state.clone();
+    let (mut fork, (mut fork_stdout, _fork_stdin)) = state.fork();
+
+    assert_eq!(state.run_with_input(b"").unwrap(), b"b");
+    while !fork.done() {
+        fork.next().unwrap();
+    }
+    drop(fork);
+    let mut forked = vec![];
+    fork_stdout.read_to_end(&mut forked).unwrap();
+    assert_eq!(forked, b"b");
+
+    clone.set_register(0, 10).unwrap();
+    while !clone.done() {
+        clone.next().unwrap();
+    }
+    assert_eq!(clone.register(0).unwrap(), 11);
+    assert_eq!(state.register(0).unwrap(), b'b' as u16);
+
+    // nothing is connected to the clone's input
+    let (state, _io) = State::init_with(&bin);
+    assert!(matches!(
+        state.clone().next(),
+        Err(vm_thingy::vm::Error::InputClosed)
+    ));
+}
+
+#[test]
+fn run_iter_yields_each_executed_instruction() {
+    let bin = vm_thingy::asm::assemble("noop\nout 65\nhalt").unwrap();
+    let (mut state, _io) = State::init_with(&bin);
+
+    let executed: Vec<(u16, Opcode)> = state.run_iter().map(Result::unwrap).collect();
+    assert_eq!(
+        executed,
+        [(0, Opcode::Noop), (2, Opcode::Out), (6, Opcode::Halt)]
+    );
+    assert_eq!(state.run_iter().count(), 0);
+
+    let bin = vm_thingy::asm::assemble("noop\npop r0\nhalt").unwrap();
+    let (mut state, _io) = State::init_with(&bin);
+    let executed: Vec<_> = state.run_iter().collect();
+    assert_eq!(executed.len(), 2);
+    assert!(matches!(executed[1], Err(vm_thingy::vm::Error::EmptyStack)));
+}
+
+// 4 bit words: literals are 0..=15, 16 and 17 name r0 and r1, anything above is invalid
+type Toy = Machine<4, 2>;
+
+fn toy(words: &[u16]) -> Toy {
+    let bin: Vec<u8> = words.iter().flat_map(|word| word.to_le_bytes()).collect();
+    Toy::builder(&bin)
+        .register(1, 5)
+        .build_with_io(std::io::empty(), std::io::sink())
+        .unwrap()
+}
+
+#[test]
+fn toy_architecture_resolves_every_operand() {
+    for word in 0..=u16::MAX {
+        // set r0 <word>
+        let mut state = toy(&[1, 16, word, 0]);
+        let result = state.next().map(|()| state.register(0).unwrap());
+        match word {
+            0..=15 => assert_eq!(result.unwrap(), word),
+            16 => assert_eq!(result.unwrap(), 0),
+            17 => assert_eq!(result.unwrap(), 5),
+            _ => assert!(matches!(
+                result,
+                Err(Error::InvalidUint15 { value, at: Some(4) }) if value == word
+            )),
+        }
+
+        // set <word> 3
+        let mut state = toy(&[1, word, 3, 0]);
+        match (word, state.next()) {
+            (16 | 17, Ok(())) => assert_eq!(state.register(word as u8 - 16), Some(3)),
+            (0..=15, Err(Error::InvalidRegister { at: Some(2), .. })) => {}
+            (18.., Err(Error::InvalidUint15 { at: Some(2), .. })) => {}
+            (_, result) => panic!("set {word} 3 gave {result:?}"),
+        }
+    }
+}
+
+#[test]
+fn toy_architecture_wraps_at_its_word_size() {
+    // add r0 15 1; not r1 0; halt
+    let mut state = toy(&[9, 16, 15, 1, 14, 17, 0, 0]);
+    while !state.done() {
+        state.next().unwrap();
+    }
+    assert_eq!(state.register(0), Some(0));
+    assert_eq!(state.register(1), Some(15));
+    assert_eq!(state.register(2), None);
+    assert!(state.set_register(0, 16).is_err());
+    assert!(state.read_word(16).is_err());
+}
+
+#[test]
+fn toy_architecture_rejects_input_it_cannot_hold() {
+    // in r0; rmem r1 r0; halt
+    let bin: Vec<u8> = [20, 16, 15, 17, 16, 0]
+        .iter()
+        .flat_map(|word: &u16| word.to_le_bytes())
+        .collect();
+    let run = |input: &'static [u8]| {
+        let mut state = Toy::builder(&bin)
+            .build_with_io(std::io::Cursor::new(input), std::io::sink())
+            .unwrap();
+        state.next().and_then(|()| state.next()).map(|()| state)
+    };
+
+    assert_eq!(run(b"\x02").unwrap().register(1), Some(15));
+    assert!(matches!(
+        run(b"z"),
+        Err(Error::InvalidUint15 {
+            value: 122,
+            at: None
+        })
+    ));
+}
+
+#[test]
+fn toy_architecture_stops_at_the_end_of_ram() {
+    // noops all the way, the last one falls through past the last word
+    let mut state = toy(&[21; 16]);
+    for _ in 0..16 {
+        state.next().unwrap();
+    }
+    assert!(matches!(
+        state.next(),
+        Err(Error::InvalidAddress {
+            value: 16,
+            at: None
+        })
+    ));
+    assert!(!state.done());
+}
+
+#[test]
+fn toy_architecture_counts_writes_within_its_ram() {
+    // wmem 15 3; halt
+    let mut state = toy(&[16, 15, 3, 0]);
+    state.enable_write_profiling(true);
+    state.next().unwrap();
+
+    assert_eq!(state.write_count(30), 1);
+    assert_eq!(state.write_count(32), 0);
+    assert_eq!(state.write_count(20000), 0);
+    assert_eq!(state.write_count(u16::MAX), 0);
+}
+
+#[test]
+fn snapshots_only_restore_into_machines_of_their_size() {
+    let mut toy = toy(&[21, 21, 0]);
+    toy.next().unwrap();
+    let bin = vm_thingy::asm::assemble("out 65\nhalt").unwrap();
+    let (mut state, _io) = State::init_with(&bin);
+    let before = state.snapshot();
+
+    assert!(matches!(
+        state.restore(&toy.snapshot()),
+        Err(Error::SnapshotMismatch)
+    ));
+    assert_eq!(state.snapshot(), before);
+    assert!(matches!(toy.restore(&before), Err(Error::SnapshotMismatch)));
+    assert_eq!(toy.program_counter(), 2);
+}
+
+#[test]
+fn interrupting_stops_before_the_next_instruction() {
+    let output = SharedBuffer::default();
+    let bin = vm_thingy::asm::assemble("out 65\nout 66\nhalt").unwrap();
+    let mut state = State::with_io(&bin, std::io::empty(), output.clone());
+    let interrupt = state.interrupt_handle();
+
+    state.next().unwrap();
+    interrupt.store(true, Ordering::Relaxed);
+    assert!(matches!(state.next(), Err(Error::Interrupted)));
+    // what was printed so far is flushed right away
+    assert_eq!(*output.0.lock().unwrap(), b"A");
+    assert_eq!(state.program_counter(), 4);
+    assert_eq!(*state.halt_reason(), HaltReason::Running);
+
+    // the flag is cleared, so the program carries on
+    while !state.done() {
+        state.next().unwrap();
+    }
+    assert_eq!(*output.0.lock().unwrap(), b"AB");
+}
+
+#[test]
+fn echoes_through_pipes_on_its_own_thread() {
+    use std::io::Write;
+    use std::sync::mpsc;
+    use std::time::Duration;
+
+    let bin = vm_thingy::asm::assemble("in r0\nout r0\njmp 0").unwrap();
+    let (mut state, (mut stdout, mut stdin)) = State::init_with(&bin);
+
+    // set up like main, the VM runs until it fails or halts
+    let (done, vm_done) = mpsc::channel();
+    std::thread::spawn(move || {
+        let result = loop {
+            if state.done() {
+                break Ok(());
+            }
+            if let Err(err) = state.next() {
+                break Err(err);
+            }
+        };
+        let _ = done.send(result);
+    });
+
+    let (echoed, echo) = mpsc::channel();
+    std::thread::spawn(move || {
+        let mut buf = [0; 6];
+        let _ = echoed.send(stdout.read_exact(&mut buf).map(|()| buf));
+    });
+
+    stdin.write_all(b"hello\n").unwrap();
+    let echo = echo
+        .recv_timeout(Duration::from_secs(5))
+        .expect("Should echo the input before timing out");
+    assert_eq!(&echo.unwrap(), b"hello\n");
+
+    // closing the input ends the program, whose next read finds nothing
+    drop(stdin);
+    let result = vm_done
+        .recv_timeout(Duration::from_secs(5))
+        .expect("Should stop once its input is closed");
+    assert!(matches!(result, Err(Error::InputClosed)));
+}
+
+#[test]
+fn ram_shows_what_the_program_wrote() {
+    let bin = vm_thingy::asm::assemble("wmem 100 7\nhalt").unwrap();
+    let (mut state, _out) = State::init_with(&bin);
+    assert_eq!(&state.ram()[..bin.len()], &bin[..]);
+
+    state.next().unwrap();
+    let changed: Vec<usize> = (0..state.ram().len())
+        .filter(|&i| state.ram()[i] != *bin.get(i).unwrap_or(&0))
+        .collect();
+    assert_eq!(changed, vec![200]);
+    assert_eq!(state.ram()[200], 7);
+}